@@ -0,0 +1,53 @@
+//! Compares the cost of reading each kind of parameter, bound and unbound.
+//!
+//! Uses only `std`, so it runs on stable with `cargo bench`.
+
+extern crate parameterize;
+
+use std::cell::Cell;
+use std::hint::black_box;
+use std::time::Instant;
+
+use parameterize::{parameter, tramp};
+
+const READS: u32 = 10_000_000;
+
+parameter! {
+	static REFCELL: usize = 1;
+	global static GLOBAL: usize = 1;
+	cell static CELL: usize = 1;
+	atomic static ATOMIC: usize = 1;
+}
+
+thread_local! {
+	static RAW: Cell<usize> = const { Cell::new(1) };
+}
+
+/// Runs `read` `READS` times and prints the time per read.
+fn bench<F: Fn() -> usize>(name: &str, read: F) {
+	for _ in 0..READS / 10 {
+		black_box(read());
+	}
+	let start = Instant::now();
+	for _ in 0..READS {
+		black_box(read());
+	}
+	let nanos = start.elapsed().as_secs_f64() * 1e9 / f64::from(READS);
+	println!["{:<28} {:>8.2} ns/read", name, nanos];
+}
+
+fn main() {
+	bench("thread_local Cell (baseline)", || RAW.with(Cell::get));
+
+	bench("Parameter", || REFCELL.get());
+	bench("GlobalParameter", || GLOBAL.get());
+	bench("CellParameter", || CELL.get());
+	bench("AtomicParameter", || ATOMIC.get());
+
+	tramp! { REFCELL: 2, GLOBAL: 2, CELL: 2, ATOMIC: 2 => {
+		bench("Parameter, bound", || REFCELL.get());
+		bench("GlobalParameter, bound", || GLOBAL.get());
+		bench("CellParameter, bound", || CELL.get());
+		bench("AtomicParameter, bound", || ATOMIC.get());
+	}}
+}
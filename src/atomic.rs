@@ -0,0 +1,234 @@
+use std::mem::ManuallyDrop;
+use std::sync::atomic::{self, Ordering};
+
+use bindings::{Bindable, Entry};
+use cell::{CellGuard, CellParameter};
+use {Guard, GuardError, ParameterError, __Bind};
+
+/// A `Copy` type with a matching atomic type from `std::sync::atomic`.
+///
+/// This is what lets an [`AtomicParameter`](struct.AtomicParameter.html) keep its default in a
+/// single atomic instead of behind a lock.
+pub trait Atomic: Copy + Send + Sync + 'static {
+	/// The atomic type holding values of `Self`.
+	type Repr: Send + Sync;
+
+	/// Reads the value with `Acquire` ordering.
+	fn load(repr: &Self::Repr) -> Self;
+
+	/// Writes the value with `Release` ordering.
+	fn store(repr: &Self::Repr, value: Self);
+}
+
+macro_rules! impl_atomic {
+	($($t:ty => $repr:ident,)*) => { $(
+		impl Atomic for $t {
+			type Repr = atomic::$repr;
+
+			#[inline]
+			fn load(repr: &atomic::$repr) -> $t {
+				repr.load(Ordering::Acquire)
+			}
+
+			#[inline]
+			fn store(repr: &atomic::$repr, value: $t) {
+				repr.store(value, Ordering::Release)
+			}
+		}
+	)* };
+}
+
+impl_atomic! {
+	bool => AtomicBool,
+	u8 => AtomicU8,
+	u16 => AtomicU16,
+	u32 => AtomicU32,
+	u64 => AtomicU64,
+	usize => AtomicUsize,
+	i8 => AtomicI8,
+	i16 => AtomicI16,
+	i32 => AtomicI32,
+	i64 => AtomicI64,
+	isize => AtomicIsize,
+}
+
+/// A [`GlobalParameter`](struct.GlobalParameter.html) for primitive values whose default is an
+/// atomic rather than a locked `Arc`.
+///
+/// Bindings live in a [`CellParameter`](struct.CellParameter.html), so reading the parameter is a
+/// thread-local load followed, when it is unbound, by an atomic load. Atomic parameters are
+/// declared with `atomic static` in [`parameter!`](macro.parameter.html); the initial value must
+/// be a constant:
+///
+/// ```ignore
+/// parameter! { pub atomic static DEPTH_LIMIT: usize = 64; }
+///
+/// DEPTH_LIMIT.set_default(128);
+/// tramp! { DEPTH_LIMIT: 8 => parse() };
+/// ```
+pub struct AtomicParameter<T: Atomic> {
+	parameter: CellParameter<Option<T>>,
+	// Atomics have no destructor; wrapping the default lets the `const` builders move it.
+	default: ManuallyDrop<T::Repr>,
+	guard: Option<Guard<T>>,
+}
+
+impl<T: Atomic> AtomicParameter<T> {
+	/// Creates an atomic parameter with the given default.
+	///
+	/// `parameter` holds the per-thread bindings and must be unbound initially.
+	pub const fn new(parameter: CellParameter<Option<T>>, default: T::Repr) -> AtomicParameter<T> {
+		AtomicParameter {
+			parameter,
+			default: ManuallyDrop::new(default),
+			guard: None,
+		}
+	}
+
+	/// Checks and converts every new binding and default with `guard`.
+	pub const fn guard(self, guard: Guard<T>) -> AtomicParameter<T> {
+		AtomicParameter {
+			guard: Some(guard),
+			..self
+		}
+	}
+
+	/// The name the parameter was declared with.
+	pub fn name(&self) -> &'static str {
+		self.parameter.name()
+	}
+
+	/// Returns the current value.
+	///
+	/// Once the thread-locals of an exiting thread are destroyed no binding is left, so this returns
+	/// the default rather than panicking.
+	#[inline]
+	pub fn get(&self) -> T {
+		match self.parameter.try_get() {
+			Ok(Some(value)) => value,
+			Ok(None) | Err(_) => T::load(&self.default),
+		}
+	}
+
+	/// Calls `f` with a reference to the current value.
+	pub fn with<F, R>(&self, f: F) -> R
+	where
+		F: FnOnce(&T) -> R,
+	{
+		f(&self.get())
+	}
+
+	/// Returns the value that the innermost binding on this thread shadows, which is the default for
+	/// the outermost binding, or `None` if the parameter is not bound on this thread.
+	pub fn outer(&self) -> Option<T> {
+		self.parameter.outer().map(|value| value.unwrap_or_else(|| T::load(&self.default)))
+	}
+
+	/// Returns the current value and every value it shadows on this thread, innermost first. The
+	/// last element is the default.
+	pub fn stack(&self) -> Vec<T> {
+		let mut values: Vec<T> = self.parameter.stack().into_iter().flatten().collect();
+		values.push(T::load(&self.default));
+		values
+	}
+
+	/// Replaces the default seen by every thread that has no binding of its own.
+	///
+	/// # Panics
+	///
+	/// Panics if the parameter's guard rejects `value`.
+	pub fn set_default(&self, value: T) {
+		if let Err(error) = self.try_set_default(value) {
+			panic!["{}", error];
+		}
+	}
+
+	/// Like [`set_default`](#method.set_default), but returns the error of a rejecting guard.
+	pub fn try_set_default(&self, value: T) -> Result<(), GuardError> {
+		T::store(&self.default, self.check(value)?);
+		Ok(())
+	}
+
+	binding_methods!(T, CellGuard<'_, Option<T>>);
+}
+
+impl<T: Atomic> __Bind for AtomicParameter<T> {
+	type Value = T;
+	type Guard<'a> = CellGuard<'a, Option<T>>;
+
+	fn __check(&self, value: T) -> Result<T, GuardError> {
+		match self.guard {
+			Some(guard) => guard(value).map_err(|error| GuardError::new(self.name(), error)),
+			None => Ok(value),
+		}
+	}
+
+	fn __set_checked(&self, value: T) -> Result<CellGuard<'_, Option<T>>, ParameterError> {
+		__Bind::__set_checked(&self.parameter, Some(value))
+	}
+}
+
+impl<T: Atomic> Bindable<T> for AtomicParameter<T> {
+	fn __entry(&'static self, value: T) -> Result<Entry, GuardError> {
+		self.parameter.__entry(Some(self.check(value)?))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use std::thread;
+
+	parameter! {
+		atomic static SHARED: bool = false;
+		atomic static SHADOWED: u32 = 1;
+		atomic static LAYERED: u32 = 1;
+		atomic static LIMIT: usize = 8, guard = |v| if v == 0 { Err("zero limit".into()) } else { Ok(v) };
+	}
+
+	#[test]
+	fn default_is_shared_by_all_threads() {
+
+		assert![!SHARED.get()];
+		SHARED.set_default(true);
+
+		assert![thread::spawn(|| SHARED.get()).join().unwrap()];
+		assert![SHARED.get()];
+	}
+
+	#[test]
+	fn binding_shadows_the_default_on_this_thread_only() {
+
+		tramp! { SHADOWED: 5 => {
+			assert_eq![SHADOWED.get(), 5];
+			assert_eq![thread::spawn(|| SHADOWED.get()).join().unwrap(), 1];
+			assert_eq![crate::thread::spawn(|| SHADOWED.get()).join().unwrap(), 5];
+
+			SHADOWED.set_default(3);
+			assert_eq![SHADOWED.get(), 5];
+		}}
+
+		assert_eq![SHADOWED.get(), 3];
+	}
+
+	#[test]
+	fn guard_checks_bindings_and_defaults() {
+
+		assert![LIMIT.try_set_default(0).is_err()];
+		assert![try_tramp! { LIMIT: 0 => () }.is_err()];
+		assert_eq![LIMIT.get(), 8];
+
+		assert_eq![tramp! { LIMIT: 2 => LIMIT.get() }, 2];
+	}
+
+	#[test]
+	fn outer_and_stack_end_with_the_default() {
+
+		assert_eq![LAYERED.outer(), None];
+		tramp! { LAYERED: 2 => {
+			assert_eq![LAYERED.outer(), Some(1)];
+			tramp! { LAYERED: 3 => assert_eq![LAYERED.stack(), [3, 2, 1]] };
+		}}
+		assert_eq![LAYERED.stack(), [1]];
+	}
+}
@@ -0,0 +1,220 @@
+use std::sync::Arc;
+
+use parameterization::{Binding, Entered, Key, Shared};
+use {GuardError, Parameter, ParameterError};
+
+/// A list of bindings assembled at run time.
+///
+/// `tramp!` binds the parameters written in the source. `Bindings` is for when the parameters
+/// are only known at run time, such as those contributed by plugins: it can be built up in a
+/// loop, merged with other lists and stored, and [`run`](#method.run) any number of times. The
+/// bindings are installed in the order they were added, so a later binding of a parameter
+/// shadows an earlier one, and restored in reverse when the closure returns or unwinds.
+///
+/// ```ignore
+/// let mut bindings = Bindings::new().bind(&VERBOSE, true);
+/// for plugin in &plugins {
+///     bindings = bindings.merge(plugin.bindings());
+/// }
+/// bindings.run(|| serve());
+/// ```
+///
+/// Values are checked by their parameter's guard when they are added. They are reference counted
+/// rather than copied on every run, and must be `Send` and `Sync`, so `Bindings` can be shared
+/// between threads.
+#[derive(Clone, Default)]
+pub struct Bindings {
+	values: Vec<(Key, Shared)>,
+}
+
+impl Bindings {
+	/// Creates an empty list of bindings.
+	pub fn new() -> Bindings {
+		Bindings::default()
+	}
+
+	/// Adds a binding of `parameter` to `value`.
+	///
+	/// # Panics
+	///
+	/// Panics if the parameter's guard rejects `value`.
+	pub fn bind<P, T>(self, parameter: &'static P, value: T) -> Bindings
+	where
+		P: Bindable<T>,
+	{
+		match self.try_bind(parameter, value) {
+			Ok(bindings) => bindings,
+			Err(error) => panic!["{}", error],
+		}
+	}
+
+	/// Like [`bind`](#method.bind), but returns the error of a rejecting guard.
+	pub fn try_bind<P, T>(mut self, parameter: &'static P, value: T) -> Result<Bindings, GuardError>
+	where
+		P: Bindable<T>,
+	{
+		let entry = parameter.__entry(value)?;
+		self.values.push((entry.key, entry.value));
+		Ok(self)
+	}
+
+	/// Appends the bindings of `other`, which therefore shadow those of `self`.
+	pub fn merge(mut self, other: Bindings) -> Bindings {
+		self.values.extend(other.values);
+		self
+	}
+
+	/// Runs `f` with the bindings installed.
+	///
+	/// # Panics
+	///
+	/// Panics if the value of one of the parameters is borrowed by an enclosing `with`, or if its
+	/// thread-local has been destroyed.
+	pub fn run<F, R>(&self, f: F) -> R
+	where
+		F: FnOnce() -> R,
+	{
+		match self.try_run(f) {
+			Ok(value) => value,
+			Err(error) => panic!["{}", error],
+		}
+	}
+
+	/// Like [`run`](#method.run), but returns an error instead of panicking when a binding cannot
+	/// be installed.
+	///
+	/// `f` is not run in that case, and the bindings installed so far are restored.
+	pub fn try_run<F, R>(&self, f: F) -> Result<R, ParameterError>
+	where
+		F: FnOnce() -> R,
+	{
+		let mut entered = Entered::new();
+		for &(key, ref value) in &self.values {
+			entered.install(key, Some(value))?;
+		}
+		Ok(f())
+	}
+}
+
+/// A parameter that [`Bindings`](struct.Bindings.html) can bind to values of type `T`.
+///
+/// This is implemented by every kind of parameter whose values can be sent between threads.
+pub trait Bindable<T>: Sync {
+	#[doc(hidden)]
+	fn __entry(&'static self, value: T) -> Result<Entry, GuardError>;
+}
+
+/// A checked, type-erased binding.
+#[doc(hidden)]
+pub struct Entry {
+	key: Key,
+	value: Shared,
+}
+
+impl Entry {
+	pub(crate) fn new(binding: &'static dyn Binding, name: &'static str, value: Shared) -> Entry {
+		Entry {
+			key: Key { binding, name },
+			value,
+		}
+	}
+}
+
+impl<T: Send + Sync + 'static> Bindable<T> for Parameter<T> {
+	fn __entry(&'static self, value: T) -> Result<Entry, GuardError> {
+		Ok(Entry::new(self.key, self.name, Arc::new(self.check(value)?)))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::Bindings;
+	use std::panic;
+
+	parameter! {
+		static FOO: u32 = 0;
+		static BAR: String = String::new();
+		static PERCENT: u32 = 0, guard = |v| if v > 100 { Err("above 100".into()) } else { Ok(v) };
+		local static LOCAL: u32 = 0;
+		cell static FLAG: bool = false;
+		global static LIMIT: u32 = 1;
+		atomic static COUNT: usize = 0;
+	}
+
+	#[test]
+	fn built_in_a_loop() {
+
+		let mut bindings = Bindings::new();
+		for (i, name) in ["a", "b", "c"].iter().enumerate() {
+			bindings = bindings.bind(&FOO, i as u32).bind(&BAR, name.to_string());
+		}
+
+		let x = bindings.run(|| format!["{}{}", FOO.get(), BAR.get()]);
+
+		assert_eq![x, "2c"];
+		assert_eq![FOO.get(), 0];
+		assert_eq![BAR.get(), ""];
+	}
+
+	#[test]
+	fn stored_and_run_repeatedly() {
+
+		let bindings = Bindings::new().bind(&FOO, 5);
+
+		assert_eq![bindings.run(|| FOO.get()), 5];
+		assert_eq![bindings.run(|| bindings.run(|| FOO.get() + 1)), 6];
+		assert_eq![tramp! { FOO: 1 => bindings.run(|| FOO.get()) }, 5];
+		assert_eq![FOO.get(), 0];
+
+		let shared = bindings.clone();
+		assert_eq![::std::thread::spawn(move || shared.run(|| FOO.get())).join().unwrap(), 5];
+	}
+
+	#[test]
+	fn merged_bindings_shadow() {
+
+		let base = Bindings::new().bind(&FOO, 1).bind(&BAR, "base".to_string());
+		let plugin = Bindings::new().bind(&FOO, 2);
+
+		assert_eq![base.clone().merge(plugin.clone()).run(|| (FOO.get(), BAR.get())), (2, "base".to_string())];
+		assert_eq![plugin.merge(base).run(|| FOO.get()), 1];
+	}
+
+	#[test]
+	fn every_kind_of_parameter() {
+
+		let bindings = Bindings::new()
+			.bind(&LOCAL, 1)
+			.bind(&FLAG, true)
+			.bind(&LIMIT, 2)
+			.bind(&COUNT, 3);
+
+		let x = bindings.run(|| (LOCAL.get(), FLAG.get(), LIMIT.get(), COUNT.get()));
+
+		assert_eq![x, (1, true, 2, 3)];
+		assert_eq![(LOCAL.get(), FLAG.get(), LIMIT.get(), COUNT.get()), (0, false, 1, 0)];
+
+		let snapshot = bindings.run(::Parameterization::current);
+		assert_eq![snapshot.enter(|| (LOCAL.get(), FLAG.get())), (0, true)];
+	}
+
+	#[test]
+	fn guard_checked_when_bound() {
+
+		assert![Bindings::new().try_bind(&PERCENT, 101).is_err()];
+		assert_eq![Bindings::new().bind(&PERCENT, 100).run(|| PERCENT.get()), 100];
+	}
+
+	#[test]
+	fn restored_after_panic() {
+
+		let bindings = Bindings::new().bind(&FOO, 1).bind(&BAR, "x".to_string()).bind(&FOO, 2);
+
+		let result = panic::catch_unwind(panic::AssertUnwindSafe(|| bindings.run(|| panic!["run"])));
+
+		assert![result.is_err()];
+		assert_eq![FOO.get(), 0];
+		assert_eq![BAR.get(), ""];
+	}
+}
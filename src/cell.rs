@@ -0,0 +1,315 @@
+use std::cell::{Cell, RefCell};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::thread::LocalKey;
+
+use bindings::{Bindable, Entry};
+use parameterization::{self, Binding, Shared};
+use restore;
+use {Guard, GuardError, ParameterError, __Bind};
+
+/// The per-thread storage behind a [`CellParameter`](struct.CellParameter.html).
+///
+/// This is normally created by the [`parameter!`](macro.parameter.html) macro.
+pub struct CellSlot<T: 'static> {
+	value: Cell<T>,
+	base: T,
+	// The values shadowed by the installed bindings, innermost last. They live in a thread-local of
+	// their own, so that this one has no destructor and reading it needs no state check.
+	shadowed: &'static LocalKey<RefCell<Vec<T>>>,
+	depth: Cell<usize>,
+	registered: Cell<bool>,
+	register: fn(&'static LocalKey<CellSlot<T>>, &'static str),
+}
+
+impl<T: Copy + Send + Sync + 'static> CellSlot<T> {
+	/// Creates the storage for a parameter with the given initial value, keeping the values its
+	/// bindings shadow in `shadowed`.
+	///
+	/// This is a `const fn` so the thread-local can be initialized without a lazy check.
+	pub const fn new(value: T, shadowed: &'static LocalKey<RefCell<Vec<T>>>) -> CellSlot<T> {
+		CellSlot {
+			value: Cell::new(value),
+			base: value,
+			shadowed,
+			depth: Cell::new(0),
+			registered: Cell::new(false),
+			register: parameterization::register,
+		}
+	}
+
+	fn install(&self, value: T) -> (T, usize) {
+		let old = self.value.replace(value);
+		// Only `outer` and `stack` read these, and nothing is left to read them once destroyed.
+		let _ = self.shadowed.try_with(|x| x.borrow_mut().push(old));
+		parameterization::count(true);
+		let depth = self.depth.get() + 1;
+		self.depth.set(depth);
+		(old, depth)
+	}
+
+	fn restore(&self, value: T, depth: usize) {
+		let current = self.depth.get();
+		self.depth.set(current - 1);
+		parameterization::count(false);
+		self.value.set(value);
+		let _ = self.shadowed.try_with(|x| x.borrow_mut().pop());
+		debug_assert!(
+			current == depth || ::std::thread::panicking(),
+			"CellGuard dropped out of order: guards of a parameter must be dropped in the \
+			 reverse order of their creation"
+		);
+	}
+}
+
+/// A parameter for `Copy` values stored in a `Cell` rather than a `RefCell`.
+///
+/// Reading it involves no borrow flag and no reference count, so with a constant initial value
+/// [`get`](#method.get) is a plain thread-local load. It is meant for flags and limits read on
+/// hot paths and is declared with `cell static` in [`parameter!`](macro.parameter.html):
+///
+/// ```ignore
+/// parameter! { pub cell static VERBOSE: bool = false; }
+///
+/// if VERBOSE.get() { .. }
+/// ```
+pub struct CellParameter<T: 'static> {
+	name: &'static str,
+	key: &'static LocalKey<CellSlot<T>>,
+	guard: Option<Guard<T>>,
+}
+
+impl<T: Copy + Send + Sync + 'static> CellParameter<T> {
+	/// Creates a parameter named `name` backed by the given thread-local.
+	pub const fn new(name: &'static str, key: &'static LocalKey<CellSlot<T>>) -> CellParameter<T> {
+		CellParameter {
+			name,
+			key,
+			guard: None,
+		}
+	}
+
+	/// Checks and converts every new value of the parameter with `guard`.
+	pub const fn guard(self, guard: Guard<T>) -> CellParameter<T> {
+		CellParameter {
+			guard: Some(guard),
+			..self
+		}
+	}
+
+	/// The name the parameter was declared with.
+	pub fn name(&self) -> &'static str {
+		self.name
+	}
+
+	/// Returns the current value.
+	#[inline]
+	pub fn get(&self) -> T {
+		self.key.with(|x| x.value.get())
+	}
+
+	/// Calls `f` with a reference to the current value.
+	pub fn with<F, R>(&self, f: F) -> R
+	where
+		F: FnOnce(&T) -> R,
+	{
+		f(&self.get())
+	}
+
+	/// Returns the value that the innermost binding on this thread shadows, or `None` if the
+	/// parameter is not bound on this thread.
+	pub fn outer(&self) -> Option<T> {
+		self.key.with(|x| x.shadowed.with(|shadowed| shadowed.borrow().last().copied()))
+	}
+
+	/// Returns the current value and every value it shadows on this thread, innermost first. The
+	/// last element is the initial value.
+	pub fn stack(&self) -> Vec<T> {
+		self.key.with(|x| {
+			let mut values = vec![x.value.get()];
+			x.shadowed.with(|shadowed| values.extend(shadowed.borrow().iter().rev()));
+			values
+		})
+	}
+
+	/// Returns the current value, or an error if this thread's thread-locals are destroyed.
+	pub fn try_get(&self) -> Result<T, ParameterError> {
+		let name = self.name;
+		self.key.try_with(|x| x.value.get()).map_err(|_| ParameterError::Destroyed { name })
+	}
+
+	/// Calls `f` with a reference to the current value, or returns an error if it cannot be read.
+	pub fn try_with<F, R>(&self, f: F) -> Result<R, ParameterError>
+	where
+		F: FnOnce(&T) -> R,
+	{
+		Ok(f(&self.try_get()?))
+	}
+
+	binding_methods!(T, CellGuard<'_, T>);
+}
+
+impl<T: Copy + Send + Sync + 'static> __Bind for CellParameter<T> {
+	type Value = T;
+	type Guard<'a> = CellGuard<'a, T>;
+
+	fn __check(&self, value: T) -> Result<T, GuardError> {
+		match self.guard {
+			Some(guard) => guard(value).map_err(|error| GuardError::new(self.name, error)),
+			None => Ok(value),
+		}
+	}
+
+	fn __set_checked(&self, value: T) -> Result<CellGuard<'_, T>, ParameterError> {
+		let name = self.name;
+		let (old, depth) = self
+			.key
+			.try_with(|x| {
+				if !x.registered.replace(true) {
+					(x.register)(self.key, name);
+				}
+				x.install(value)
+			})
+			.map_err(|_| ParameterError::Destroyed { name })?;
+		Ok(CellGuard {
+			parameter: self,
+			old,
+			depth,
+			thread_bound: PhantomData,
+		})
+	}
+}
+
+/// Restores the previous value of a [`CellParameter`](struct.CellParameter.html) when dropped.
+#[must_use]
+pub struct CellGuard<'a, T: Copy + Send + Sync + 'static> {
+	parameter: &'a CellParameter<T>,
+	old: T,
+	depth: usize,
+	thread_bound: PhantomData<*const ()>,
+}
+
+impl<'a, T: Copy + Send + Sync + 'static> Drop for CellGuard<'a, T> {
+	fn drop(&mut self) {
+		let (old, depth) = (self.old, self.depth);
+		if self.parameter.key.try_with(|x| x.restore(old, depth)).is_err() {
+			restore::failed(&ParameterError::Destroyed { name: self.parameter.name });
+		}
+	}
+}
+
+impl<T: Copy + Send + Sync + 'static> Binding for LocalKey<CellSlot<T>> {
+	fn capture(&'static self) -> Option<Shared> {
+		self.try_with(|x| {
+			if x.depth.get() > 0 {
+				Some(Arc::new(x.value.get()) as Shared)
+			} else {
+				None
+			}
+		})
+		.unwrap_or(None)
+	}
+
+	fn install(
+		&'static self,
+		name: &'static str,
+		value: Option<&Shared>,
+	) -> Result<(Option<Shared>, usize), ParameterError> {
+		self.try_with(|x| {
+			let value = match value {
+				Some(value) => *value.downcast_ref::<T>().expect("captured value has the wrong type"),
+				None => x.base,
+			};
+			if !x.registered.replace(true) {
+				(x.register)(self, name);
+			}
+			let (old, depth) = x.install(value);
+			(Some(Arc::new(old) as Shared), depth)
+		})
+		.map_err(|_| ParameterError::Destroyed { name })
+	}
+
+	fn restore(&'static self, old: Option<Shared>, depth: usize) {
+		let old = old.expect("saved value is missing");
+		let old = *old.downcast_ref::<T>().expect("saved value has the wrong type");
+		if self.try_with(|x| x.restore(old, depth)).is_err() {
+			restore::failed(&"a thread-local was destroyed before its parameterization exited");
+		}
+	}
+}
+
+impl<T: Copy + Send + Sync + 'static> Bindable<T> for CellParameter<T> {
+	fn __entry(&'static self, value: T) -> Result<Entry, GuardError> {
+		Ok(Entry::new(self.key, self.name, Arc::new(self.check(value)?)))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::CellSlot;
+	use std::mem;
+	use std::thread;
+
+	parameter! {
+		cell static VERBOSE: bool = false;
+		cell static DEPTH: usize = 0, guard = |v| if v > 64 { Err("too deep".into()) } else { Ok(v) };
+	}
+
+	#[test]
+	fn tramp_and_get() {
+
+		let x = tramp! { VERBOSE: true, DEPTH: 2 => {
+			assert![VERBOSE.get()];
+			tramp! { DEPTH: DEPTH.get() + 1 => DEPTH.get() }
+		}};
+
+		assert_eq![x, 3];
+		assert![!VERBOSE.get()];
+		assert_eq![DEPTH.get(), 0];
+	}
+
+	#[test]
+	fn guard() {
+
+		assert![try_tramp! { DEPTH: 65 => () }.is_err()];
+		assert_eq![DEPTH.with(|x| *x), 0];
+	}
+
+	#[test]
+	fn inherited_by_threads() {
+
+		let x = tramp! { VERBOSE: true => crate::thread::spawn(|| VERBOSE.get()) };
+		assert![x.join().unwrap()];
+
+		let y = tramp! { VERBOSE: true => thread::spawn(|| VERBOSE.get()) };
+		assert![!y.join().unwrap()];
+	}
+
+	#[test]
+	fn parameterization_resets_unbound_cells() {
+
+		let snapshot = crate::Parameterization::current();
+
+		tramp! { DEPTH: 5 => snapshot.enter(|| assert_eq![DEPTH.get(), 0]) };
+	}
+
+	#[test]
+	fn slot_has_no_destructor() {
+
+		// Otherwise every read of the thread-local would check whether it is still alive.
+		assert![!mem::needs_drop::<CellSlot<usize>>()];
+	}
+
+	#[test]
+	fn outer_and_stack() {
+
+		tramp! { DEPTH: 1 => tramp! { DEPTH: DEPTH.get() + 1 => {
+			assert_eq![DEPTH.outer(), Some(1)];
+			assert_eq![DEPTH.stack(), [2, 1, 0]];
+		}}}
+
+		assert_eq![DEPTH.outer(), None];
+		assert_eq![DEPTH.stack(), [0]];
+	}
+}
@@ -0,0 +1,106 @@
+use std::error::Error;
+use std::fmt;
+
+/// Checks and converts every value a parameter is set to, like the guard of Racket's
+/// `make-parameter`.
+///
+/// The guard receives the new value and returns the value to install, or an error that rejects
+/// the binding. It is not applied to the initial value.
+pub type Guard<T> = fn(T) -> Result<T, Box<dyn Error + Send + Sync>>;
+
+/// A parameter's guard rejected a value.
+#[derive(Debug)]
+pub struct GuardError {
+	parameter: &'static str,
+	error: Box<dyn Error + Send + Sync>,
+}
+
+impl GuardError {
+	pub(crate) fn new(parameter: &'static str, error: Box<dyn Error + Send + Sync>) -> GuardError {
+		GuardError { parameter, error }
+	}
+
+	/// The name of the parameter whose guard rejected the value.
+	pub fn parameter(&self) -> &'static str {
+		self.parameter
+	}
+}
+
+impl fmt::Display for GuardError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "invalid value for parameter `{}`: {}", self.parameter, self.error)
+	}
+}
+
+impl Error for GuardError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		Some(&*self.error)
+	}
+}
+
+/// A parameter could not be read or bound.
+#[derive(Debug)]
+pub enum ParameterError {
+	/// The parameter is required and has no binding on this thread.
+	Unbound {
+		/// The name the parameter was declared with.
+		name: &'static str,
+	},
+	/// The parameter's value is borrowed, for instance by an enclosing
+	/// [`with`](struct.Parameter.html#method.with), so it cannot be rebound.
+	Borrowed {
+		/// The name the parameter was declared with.
+		name: &'static str,
+	},
+	/// The parameter was used while this thread's thread-locals were being destroyed.
+	Destroyed {
+		/// The name the parameter was declared with.
+		name: &'static str,
+	},
+	/// The parameter's guard rejected the new value.
+	Invalid(GuardError),
+}
+
+impl ParameterError {
+	/// The name of the parameter the error is about.
+	pub fn parameter(&self) -> &'static str {
+		match *self {
+			ParameterError::Unbound { name }
+			| ParameterError::Borrowed { name }
+			| ParameterError::Destroyed { name } => name,
+			ParameterError::Invalid(ref error) => error.parameter(),
+		}
+	}
+}
+
+impl From<GuardError> for ParameterError {
+	fn from(error: GuardError) -> ParameterError {
+		ParameterError::Invalid(error)
+	}
+}
+
+impl fmt::Display for ParameterError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ParameterError::Unbound { name } => {
+				write!(f, "parameter `{}` is required but not bound", name)
+			}
+			ParameterError::Borrowed { name } => {
+				write!(f, "parameter `{}` cannot be rebound while its value is borrowed", name)
+			}
+			ParameterError::Destroyed { name } => {
+				write!(f, "parameter `{}` was used after its thread-local storage was destroyed", name)
+			}
+			ParameterError::Invalid(ref error) => error.fmt(f),
+		}
+	}
+}
+
+impl Error for ParameterError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match *self {
+			ParameterError::Invalid(ref error) => error.source(),
+			_ => None,
+		}
+	}
+}
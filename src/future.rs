@@ -0,0 +1,329 @@
+//! Carrying parameter bindings into futures and streams.
+//!
+//! A `tramp!` block restores its bindings as soon as it exits, but a future created inside it
+//! usually runs much later, after being handed to an executor. [`Parameterized`] captures the
+//! bindings when it is created and installs them around every `poll`, or every `poll_next` of a
+//! [`Stream`].
+//!
+//! A binding made inside the future itself must not be held across an `.await`: a `tramp!` block
+//! or [`ParamGuard`](../struct.ParamGuard.html) spanning one would still be installed when the poll
+//! returns, and would leak into whatever the thread runs next. Polling a [`Parameterized`] future
+//! that does this panics. To bind parameters around code that awaits, wrap that code in a future
+//! of its own instead:
+//!
+//! ```ignore
+//! let limited = tramp! { LIMIT: 8 => fetch_all().with_parameters() };
+//! let pages = limited.await;
+//! ```
+//!
+//! ```ignore
+//! use parameterize::future::FutureExt;
+//!
+//! let task = tramp! { VERBOSE: true => fetch().with_parameters() };
+//! executor.spawn(task);
+//! ```
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use parameterization::{self, Parameterization};
+
+/// A future or stream that runs its inner future or stream under the bindings that were current
+/// when it was created.
+///
+/// The bindings are installed for the duration of each poll and restored before it returns, so
+/// they never leak into other tasks sharing the executor's thread. The future may be polled on
+/// any thread; it is `Send` whenever the inner future is. Like
+/// [`Parameterization::enter`](../struct.Parameterization.html#method.enter), polling panics if
+/// the bindings cannot be installed, such as when it happens inside a `with` of one of the
+/// parameters.
+///
+/// Polling also panics if the inner future returns with a binding of its own still installed,
+/// which is what a `tramp!` block or guard held across an `.await` does.
+#[must_use = "futures do nothing unless polled"]
+pub struct Parameterized<F> {
+	future: F,
+	parameterization: Parameterization,
+}
+
+impl<F> Parameterized<F> {
+	/// Wraps `future`, or a stream, capturing the current bindings.
+	pub fn new(future: F) -> Parameterized<F> {
+		Parameterized {
+			future,
+			parameterization: Parameterization::current(),
+		}
+	}
+}
+
+impl<F: Future> Future for Parameterized<F> {
+	type Output = F::Output;
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<F::Output> {
+		// The inner future is structurally pinned: it is never moved out of `self`.
+		let this = unsafe { self.get_unchecked_mut() };
+		let future = unsafe { Pin::new_unchecked(&mut this.future) };
+		this.parameterization.enter(|| checked(|| future.poll(cx)))
+	}
+}
+
+/// Runs a poll, checking that it leaves no binding of its own installed.
+///
+/// Otherwise the binding would be restored out of order by the next poll, after leaking into
+/// whatever else the thread runs in between.
+fn checked<F, R>(poll: F) -> R
+where
+	F: FnOnce() -> R,
+{
+	let installed = parameterization::installed();
+	let result = poll();
+	assert!(
+		parameterization::installed() == installed,
+		"parameterize: a future returned from `poll` with a binding of its own still installed; a \
+		 `tramp!` block or guard must not be held across an `.await`"
+	);
+	result
+}
+
+/// Adds [`with_parameters`](#method.with_parameters) to every future.
+pub trait FutureExt: Future + Sized {
+	/// Captures the current bindings and installs them around every poll of this future.
+	fn with_parameters(self) -> Parameterized<Self> {
+		Parameterized::new(self)
+	}
+}
+
+impl<F: Future> FutureExt for F {}
+
+/// An asynchronous sequence of values, shaped like the `Stream` trait of the `futures` crate.
+///
+/// Implement this for an async stream type to be able to wrap it with
+/// [`with_parameters`](trait.StreamExt.html#method.with_parameters).
+pub trait Stream {
+	/// The type of the values yielded.
+	type Item;
+
+	/// Attempts to pull out the next value, registering the task for wakeup if none is ready yet.
+	/// Returns `Poll::Ready(None)` once the stream is exhausted.
+	fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>>;
+
+	/// Returns bounds on the number of values left, like `Iterator::size_hint`.
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		(0, None)
+	}
+}
+
+impl<S: Stream> Stream for Parameterized<S> {
+	type Item = S::Item;
+
+	fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<S::Item>> {
+		// The inner stream is structurally pinned: it is never moved out of `self`.
+		let this = unsafe { self.get_unchecked_mut() };
+		let stream = unsafe { Pin::new_unchecked(&mut this.future) };
+		this.parameterization.enter(|| checked(|| stream.poll_next(cx)))
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		self.future.size_hint()
+	}
+}
+
+/// Adds [`with_parameters`](#method.with_parameters) to every [`Stream`](trait.Stream.html).
+pub trait StreamExt: Stream + Sized {
+	/// Captures the current bindings and installs them around every `poll_next` of this stream.
+	fn with_parameters(self) -> Parameterized<Self> {
+		Parameterized::new(self)
+	}
+}
+
+impl<S: Stream> StreamExt for S {}
+
+#[cfg(test)]
+mod tests {
+
+	use super::{FutureExt, Stream, StreamExt};
+	use std::cell::RefCell;
+	use std::collections::VecDeque;
+	use std::future::{self, Future};
+	use std::mem;
+	use std::panic;
+	use std::pin::Pin;
+	use std::rc::Rc;
+	use std::sync::Arc;
+	use std::task::{Context, Poll, Wake, Waker};
+
+	parameter! {
+		static FOO: u32 = 0;
+		static BAR: &'static str = "";
+	}
+
+	struct NoopWaker;
+
+	impl Wake for NoopWaker {
+		fn wake(self: Arc<Self>) {}
+	}
+
+	/// Polls every task in turn until all of them are done.
+	fn run(tasks: Vec<Pin<Box<dyn Future<Output = ()>>>>) {
+		let waker = Waker::from(Arc::new(NoopWaker));
+		let mut cx = Context::from_waker(&waker);
+		let mut queue: VecDeque<_> = tasks.into_iter().collect();
+		while let Some(mut task) = queue.pop_front() {
+			if task.as_mut().poll(&mut cx).is_pending() {
+				queue.push_back(task);
+			}
+		}
+	}
+
+	/// Records the value of `FOO` on each poll, finishing after `polls` polls.
+	struct Record {
+		seen: Rc<RefCell<Vec<u32>>>,
+		polls: usize,
+	}
+
+	impl Future for Record {
+		type Output = ();
+
+		fn poll(mut self: Pin<&mut Self>, _: &mut Context) -> Poll<()> {
+			self.seen.borrow_mut().push(FOO.get());
+			self.polls -= 1;
+			if self.polls == 0 {
+				Poll::Ready(())
+			} else {
+				Poll::Pending
+			}
+		}
+	}
+
+	fn record(seen: &Rc<RefCell<Vec<u32>>>, polls: usize) -> Record {
+		Record {
+			seen: seen.clone(),
+			polls,
+		}
+	}
+
+	#[test]
+	fn bindings_survive_the_scope() {
+
+		let seen = Rc::new(RefCell::new(Vec::new()));
+		let task = tramp! { FOO: 7 => record(&seen, 3).with_parameters() };
+
+		run(vec![Box::pin(task)]);
+
+		assert_eq![*seen.borrow(), [7, 7, 7]];
+		assert_eq![FOO.get(), 0];
+	}
+
+	#[test]
+	fn interleaved_tasks_keep_their_own_bindings() {
+
+		let a = Rc::new(RefCell::new(Vec::new()));
+		let b = Rc::new(RefCell::new(Vec::new()));
+		let c = Rc::new(RefCell::new(Vec::new()));
+
+		let first = tramp! { FOO: 1 => record(&a, 2).with_parameters() };
+		let second = tramp! { FOO: 2 => record(&b, 2).with_parameters() };
+
+		tramp! { FOO: 3 => run(vec![Box::pin(first), Box::pin(second), Box::pin(record(&c, 2))]) };
+
+		assert_eq![*a.borrow(), [1, 1]];
+		assert_eq![*b.borrow(), [2, 2]];
+		assert_eq![*c.borrow(), [3, 3]];
+	}
+
+	#[test]
+	fn unbound_parameters_see_their_initial_value() {
+
+		let seen = Rc::new(RefCell::new(Vec::new()));
+		let task = tramp! { BAR: "bar" => record(&seen, 1).with_parameters() };
+
+		tramp! { FOO: 9 => run(vec![Box::pin(task)]) };
+
+		assert_eq![*seen.borrow(), [0]];
+	}
+
+	#[test]
+	fn send_whenever_the_inner_future_is() {
+
+		fn assert_send<T: Send>(_: &T) {}
+
+		assert_send(&tramp! { FOO: 1 => future::ready(FOO.get()).with_parameters() });
+	}
+
+	/// Binds `FOO` on its first poll and keeps the guard until its second, like a `tramp!` block
+	/// spanning an `.await`.
+	struct HoldAcrossPolls(Option<::ParamGuard<'static, u32>>);
+
+	impl Future for HoldAcrossPolls {
+		type Output = u32;
+
+		fn poll(mut self: Pin<&mut Self>, _: &mut Context) -> Poll<u32> {
+			match self.0.take() {
+				None => {
+					self.0 = Some(FOO.set_scoped(5));
+					Poll::Pending
+				}
+				Some(_guard) => Poll::Ready(FOO.get()),
+			}
+		}
+	}
+
+	#[test]
+	fn binding_held_across_polls_is_reported() {
+
+		let waker = Waker::from(Arc::new(NoopWaker));
+		let mut cx = Context::from_waker(&waker);
+		let mut task = Box::pin(tramp! { FOO: 1 => HoldAcrossPolls(None).with_parameters() });
+
+		let result = panic::catch_unwind(panic::AssertUnwindSafe(|| task.as_mut().poll(&mut cx)));
+
+		let error = result.unwrap_err();
+		let message = error.downcast_ref::<&str>().copied().unwrap_or_default();
+		assert![message.contains("must not be held across an `.await`"), "{}", message];
+		// The guard inside can no longer be restored in order.
+		mem::forget(task);
+	}
+
+	/// Yields the value of `FOO` on every other poll, `items` times.
+	struct Counter {
+		items: usize,
+		ready: bool,
+	}
+
+	impl Stream for Counter {
+		type Item = u32;
+
+		fn poll_next(mut self: Pin<&mut Self>, _: &mut Context) -> Poll<Option<u32>> {
+			if self.items == 0 {
+				return Poll::Ready(None);
+			}
+			self.ready = !self.ready;
+			if self.ready {
+				self.items -= 1;
+				Poll::Ready(Some(FOO.get()))
+			} else {
+				Poll::Pending
+			}
+		}
+	}
+
+	#[test]
+	fn stream_polled_after_the_scope() {
+
+		let waker = Waker::from(Arc::new(NoopWaker));
+		let mut cx = Context::from_waker(&waker);
+		let mut stream = Box::pin(tramp! { FOO: 4 => Counter { items: 2, ready: false }.with_parameters() });
+
+		let mut seen = Vec::new();
+		tramp! { FOO: 5 => loop {
+			match stream.as_mut().poll_next(&mut cx) {
+				Poll::Ready(Some(x)) => seen.push(x),
+				Poll::Ready(None) => break,
+				Poll::Pending => assert_eq![FOO.get(), 5],
+			}
+		}}
+
+		assert_eq![seen, [4, 4]];
+	}
+}
@@ -0,0 +1,251 @@
+use std::mem;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use std::panic::Location;
+
+use bindings::{Bindable, Entry};
+use {GuardError, ParamGuard, Parameter, ParameterError, __Bind};
+
+/// A parameter with a process-wide default that bindings shadow per thread.
+///
+/// Reading the parameter returns the innermost binding on the current thread if there is one,
+/// and the global default otherwise. [`set_default`](#method.set_default) changes the default for
+/// every thread at once, while `tramp!` and [`set_scoped`](#method.set_scoped) only override it
+/// within their dynamic extent.
+///
+/// Global parameters are declared with `global static` in [`parameter!`](macro.parameter.html):
+///
+/// ```ignore
+/// parameter! { pub global static TIMEOUT: Duration = Duration::from_secs(30); }
+///
+/// TIMEOUT.set_default(Duration::from_secs(5));
+/// tramp! { TIMEOUT: Duration::from_secs(1) => connect() };
+/// ```
+pub struct GlobalParameter<T: 'static> {
+	parameter: Parameter<T>,
+	init: fn() -> T,
+	default: OnceLock<RwLock<Arc<T>>>,
+}
+
+impl<T: Send + Sync + 'static> GlobalParameter<T> {
+	/// Creates a global parameter whose default is computed by `init` on first use.
+	///
+	/// `parameter` holds the per-thread bindings and its guard, if any, also checks new defaults.
+	pub const fn new(parameter: Parameter<T>, init: fn() -> T) -> GlobalParameter<T> {
+		GlobalParameter {
+			parameter,
+			init,
+			default: OnceLock::new(),
+		}
+	}
+
+	/// The name the parameter was declared with.
+	pub fn name(&self) -> &'static str {
+		self.parameter.name()
+	}
+
+	fn default_lock(&self) -> &RwLock<Arc<T>> {
+		self.default.get_or_init(|| RwLock::new(Arc::new((self.init)())))
+	}
+
+	/// Returns the binding on this thread, or the default if there is none.
+	///
+	/// Once the thread-locals of an exiting thread are destroyed no binding is left, so the default
+	/// is returned as well.
+	fn current(&self) -> Result<Arc<T>, ParameterError> {
+		match self.parameter.bound() {
+			Ok(Some(value)) => Ok(value),
+			Ok(None) | Err(ParameterError::Destroyed { .. }) => Ok(self.default()),
+			Err(error) => Err(error),
+		}
+	}
+
+	fn default(&self) -> Arc<T> {
+		match self.default_lock().read() {
+			Ok(default) => default.clone(),
+			Err(poisoned) => poisoned.into_inner().clone(),
+		}
+	}
+
+	/// Returns a copy of the current value.
+	#[track_caller]
+	pub fn get(&self) -> T
+	where
+		T: Clone,
+	{
+		self.with(T::clone)
+	}
+
+	/// Returns a copy of the current value, or an error if it cannot be read.
+	pub fn try_get(&self) -> Result<T, ParameterError>
+	where
+		T: Clone,
+	{
+		self.try_with(T::clone)
+	}
+
+	/// Calls `f` with a reference to the current value.
+	///
+	/// No lock is held while `f` runs, so it may change the default.
+	#[track_caller]
+	pub fn with<F, R>(&self, f: F) -> R
+	where
+		F: FnOnce(&T) -> R,
+	{
+		match self.try_with(f) {
+			Ok(value) => value,
+			Err(error) => panic!["{}, read at {}", error, Location::caller()],
+		}
+	}
+
+	/// Calls `f` with a reference to the current value, or returns an error if it cannot be read.
+	pub fn try_with<F, R>(&self, f: F) -> Result<R, ParameterError>
+	where
+		F: FnOnce(&T) -> R,
+	{
+		let value = self.current()?;
+		Ok(f(&value))
+	}
+
+	/// Returns a copy of the value that the innermost binding on this thread shadows, which is the
+	/// default for the outermost binding, or `None` if the parameter is not bound on this thread.
+	pub fn outer(&self) -> Option<T>
+	where
+		T: Clone,
+	{
+		match self.parameter.shadowed() {
+			Some(Some(value)) => Some(T::clone(&value)),
+			Some(None) => Some(T::clone(&self.default())),
+			None => None,
+		}
+	}
+
+	/// Returns copies of the current value and of every value it shadows on this thread, innermost
+	/// first. The last element is the default.
+	pub fn stack(&self) -> Vec<T>
+	where
+		T: Clone,
+	{
+		let mut values = self.parameter.stack();
+		values.push(T::clone(&self.default()));
+		values
+	}
+
+	/// Replaces the default seen by every thread that has no binding of its own.
+	///
+	/// # Panics
+	///
+	/// Panics if the parameter's guard rejects `value`.
+	pub fn set_default(&self, value: T) {
+		if let Err(error) = self.try_set_default(value) {
+			panic!["{}", error];
+		}
+	}
+
+	/// Like [`set_default`](#method.set_default), but returns the error of a rejecting guard.
+	pub fn try_set_default(&self, value: T) -> Result<(), GuardError> {
+		let value = Arc::new(self.parameter.check(value)?);
+		// The old default is dropped after the lock is released.
+		let _old = match self.default_lock().write() {
+			Ok(mut default) => mem::replace(&mut *default, value),
+			Err(poisoned) => mem::replace(&mut *poisoned.into_inner(), value),
+		};
+		Ok(())
+	}
+
+	binding_methods!(T, ParamGuard<'_, T>);
+}
+
+impl<T: Send + Sync + 'static> __Bind for GlobalParameter<T> {
+	type Value = T;
+	type Guard<'a> = ParamGuard<'a, T>;
+
+	fn __check(&self, value: T) -> Result<T, GuardError> {
+		self.parameter.check(value)
+	}
+
+	fn __set_checked(&self, value: T) -> Result<ParamGuard<'_, T>, ParameterError> {
+		__Bind::__set_checked(&self.parameter, value)
+	}
+}
+
+impl<T: Send + Sync + 'static> Bindable<T> for GlobalParameter<T> {
+	fn __entry(&'static self, value: T) -> Result<Entry, GuardError> {
+		self.parameter.__entry(value)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use std::thread;
+
+	parameter! {
+		global static SHARED: u32 = 1;
+		global static SHADOWED: u32 = 1;
+		global static INHERITED: u32 = 1;
+		global static LAYERED: u32 = 1;
+		global static NAME: String = "init".to_string(), guard = |v: String| {
+			if v.is_empty() { Err("empty name".into()) } else { Ok(v) }
+		};
+	}
+
+	#[test]
+	fn default_is_shared_by_all_threads() {
+
+		assert_eq![SHARED.get(), 1];
+		SHARED.set_default(2);
+
+		assert_eq![thread::spawn(|| SHARED.get()).join().unwrap(), 2];
+		assert_eq![SHARED.get(), 2];
+	}
+
+	#[test]
+	fn binding_shadows_the_default_on_this_thread_only() {
+
+		tramp! { SHADOWED: 5 => {
+			assert_eq![SHADOWED.get(), 5];
+			assert_eq![thread::spawn(|| SHADOWED.get()).join().unwrap(), 1];
+
+			SHADOWED.set_default(3);
+			assert_eq![SHADOWED.get(), 5];
+			tramp! { SHADOWED: 6 => assert_eq![SHADOWED.get(), 6] };
+			assert_eq![SHADOWED.get(), 5];
+		}}
+
+		assert_eq![SHADOWED.get(), 3];
+	}
+
+	#[test]
+	fn bindings_are_inherited_and_defaults_are_not_captured() {
+
+		let child = tramp! { INHERITED: 7 => crate::thread::spawn(|| INHERITED.get()) };
+		assert_eq![child.join().unwrap(), 7];
+
+		let snapshot = crate::Parameterization::current();
+		INHERITED.set_default(8);
+		tramp! { INHERITED: 9 => snapshot.enter(|| assert_eq![INHERITED.get(), 8]) };
+	}
+
+	#[test]
+	fn guard_checks_bindings_and_defaults() {
+
+		assert![NAME.try_set_default(String::new()).is_err()];
+		assert![try_tramp! { NAME: String::new() => () }.is_err()];
+		assert_eq![NAME.get(), "init"];
+
+		NAME.with(|x| NAME.set_default(format!["{}!", x]));
+		assert_eq![NAME.get(), "init!"];
+	}
+
+	#[test]
+	fn outer_and_stack_end_with_the_default() {
+
+		assert_eq![LAYERED.outer(), None];
+		tramp! { LAYERED: 2 => {
+			assert_eq![LAYERED.outer(), Some(1)];
+			tramp! { LAYERED: 3 => assert_eq![LAYERED.stack(), [3, 2, 1]] };
+		}}
+		assert_eq![LAYERED.stack(), [1]];
+	}
+}
@@ -0,0 +1,121 @@
+//! Carrying parameter bindings into lazy iterators.
+//!
+//! An iterator chain built inside a `tramp!` block does no work until it is consumed, which often
+//! happens after the block has exited, so closures in the chain would see the wrong values.
+//! [`Parameterized`] captures the bindings when it is created and installs them around every call
+//! to `next`. It should be the last adaptor of the chain: adaptors applied after it run under the
+//! bindings of whoever consumes the iterator.
+//!
+//! ```ignore
+//! use parameterize::iter::IteratorExt;
+//!
+//! let scaled = tramp! { SCALE: 10 => values.iter().map(|x| x * SCALE.get()).with_parameters() };
+//! let total: u32 = scaled.sum();
+//! ```
+
+use std::iter::FusedIterator;
+
+use parameterization::Parameterization;
+
+/// An iterator that advances its inner iterator under the bindings that were current when it was
+/// created.
+///
+/// The bindings are installed for the duration of each `next` and restored before it returns, so
+/// the consumer's own bindings are unaffected between items. Like
+/// [`Parameterization::enter`](../struct.Parameterization.html#method.enter), `next` panics if they
+/// cannot be installed, such as when it is called inside a `with` of one of the parameters.
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+#[derive(Clone)]
+pub struct Parameterized<I> {
+	iter: I,
+	parameterization: Parameterization,
+}
+
+impl<I: Iterator> Parameterized<I> {
+	/// Wraps `iter`, capturing the current bindings.
+	pub fn new(iter: I) -> Parameterized<I> {
+		Parameterized {
+			iter,
+			parameterization: Parameterization::current(),
+		}
+	}
+}
+
+impl<I: Iterator> Iterator for Parameterized<I> {
+	type Item = I::Item;
+
+	fn next(&mut self) -> Option<I::Item> {
+		let iter = &mut self.iter;
+		self.parameterization.enter(|| iter.next())
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		self.iter.size_hint()
+	}
+}
+
+impl<I: DoubleEndedIterator> DoubleEndedIterator for Parameterized<I> {
+	fn next_back(&mut self) -> Option<I::Item> {
+		let iter = &mut self.iter;
+		self.parameterization.enter(|| iter.next_back())
+	}
+}
+
+impl<I: ExactSizeIterator> ExactSizeIterator for Parameterized<I> {}
+
+impl<I: FusedIterator> FusedIterator for Parameterized<I> {}
+
+/// Adds [`with_parameters`](#method.with_parameters) to every iterator.
+pub trait IteratorExt: Iterator + Sized {
+	/// Captures the current bindings and installs them around every `next` of this iterator.
+	fn with_parameters(self) -> Parameterized<Self> {
+		Parameterized::new(self)
+	}
+}
+
+impl<I: Iterator> IteratorExt for I {}
+
+#[cfg(test)]
+mod tests {
+
+	use super::IteratorExt;
+
+	parameter! {
+		static FOO: u32 = 0;
+		static BAR: &'static str = "";
+	}
+
+	#[test]
+	fn consumed_after_the_scope() {
+
+		let values = [1, 2, 3];
+		let lazy = tramp! { FOO: 10 => values.iter().map(|x| x * FOO.get()).with_parameters() };
+
+		assert_eq![lazy.collect::<Vec<_>>(), [10, 20, 30]];
+		assert_eq![FOO.get(), 0];
+	}
+
+	#[test]
+	fn consumer_keeps_its_own_bindings() {
+
+		let lazy = tramp! { FOO: 1 => (0..3).map(|_| (FOO.get(), BAR.get())).with_parameters() };
+
+		tramp! { FOO: 2, BAR: "consumer" => {
+			for item in lazy {
+				assert_eq![item, (1, "")];
+				assert_eq![(FOO.get(), BAR.get()), (2, "consumer")];
+			}
+		}}
+	}
+
+	#[test]
+	fn both_ends_and_size() {
+
+		let mut lazy = tramp! { FOO: 5 => (0..4).map(|x| x + FOO.get()).with_parameters() };
+
+		assert_eq![lazy.len(), 4];
+		assert_eq![lazy.next_back(), Some(8)];
+		assert_eq![lazy.next(), Some(5)];
+		assert_eq![lazy.len(), 2];
+	}
+}
@@ -19,10 +19,10 @@
 //! A cleaner way of doing this may be:
 //!
 //! ```ignore
-//! thread_local! { static A: RefCell<i32> = RefCell::new(0); }
+//! parameter! { static A: i32 = 0; }
 //! fn f() { g(); };
 //! fn g() { h(); };
-//! fn h() { A.with(|a| i(*a.borrow())); };
+//! fn h() { i(A.get()); };
 //! fn i(a: i32) { println!["Only I use {}", a]; };
 //!
 //! fn main() {
@@ -33,61 +33,1041 @@
 //!
 //! This is useful for deep call chains when objects can't store the value for you. The intermediate functions are much cleaner.
 
-#[macro_use]
-extern crate scopeguard;
+use std::cell::{BorrowMutError, Cell, RefCell};
+use std::marker::PhantomData;
+use std::mem;
+use std::panic::Location;
+use std::sync::Arc;
+use std::thread::LocalKey;
 
+/// The per-thread storage behind a [`Parameter`](struct.Parameter.html).
+///
+/// This is normally created by the [`parameter!`](macro.parameter.html) macro.
+pub struct Slot<T: 'static> {
+	value: RefCell<Option<Arc<T>>>,
+	base: Option<Arc<T>>,
+	// The values shadowed by the installed bindings, innermost last.
+	shadowed: RefCell<Vec<Option<Arc<T>>>>,
+	deferred: RefCell<Vec<Option<Arc<T>>>>,
+	// The length of `deferred`, so that reads can skip flushing without borrowing it.
+	pending: Cell<usize>,
+	depth: Cell<usize>,
+	registered: Cell<bool>,
+	register: Option<Register<T>>,
+}
+
+/// Records a slot the first time its parameter is bound, given the parameter's name.
+type Register<T> = fn(&'static LocalKey<Slot<T>>, &'static str);
+
+impl<T: Send + Sync + 'static> Slot<T> {
+	/// Creates the storage for a parameter with the given initial value.
+	///
+	/// The parameter's bindings are captured by [`Parameterization`](struct.Parameterization.html)
+	/// and so carried into spawned threads.
+	pub fn new(value: T) -> Slot<T> {
+		Slot::with_register(Some(Arc::new(value)), Some(parameterization::register))
+	}
+
+	/// Creates the storage for a parameter that has no value of its own until it is bound.
+	///
+	/// This backs required parameters and the override layer of a
+	/// [`GlobalParameter`](struct.GlobalParameter.html).
+	pub fn empty() -> Slot<T> {
+		Slot::with_register(None, Some(parameterization::register))
+	}
+}
+
+impl<T: 'static> Slot<T> {
+	/// Creates the storage for a parameter whose bindings never leave the current thread.
+	///
+	/// A [`Parameterization`](struct.Parameterization.html) neither captures nor replaces the
+	/// value of such a parameter, so `T` need not be `Send` or `Sync`.
+	pub fn local(value: T) -> Slot<T> {
+		Slot::with_register(Some(Arc::new(value)), None)
+	}
+
+	fn with_register(base: Option<Arc<T>>, register: Option<Register<T>>) -> Slot<T> {
+		Slot {
+			value: RefCell::new(base.clone()),
+			base,
+			shadowed: RefCell::new(Vec::new()),
+			deferred: RefCell::new(Vec::new()),
+			pending: Cell::new(0),
+			depth: Cell::new(0),
+			registered: Cell::new(false),
+			register,
+		}
+	}
+
+	fn install(&self, value: &mut Option<Arc<T>>) -> Result<usize, BorrowMutError> {
+		mem::swap(&mut *self.value.try_borrow_mut()?, value);
+		self.shadowed.borrow_mut().push(value.clone());
+		parameterization::count(true);
+		let depth = self.depth.get() + 1;
+		self.depth.set(depth);
+		Ok(depth)
+	}
+
+	/// Swaps `value` back in, or defers that while the value is borrowed. Never panics in release
+	/// builds.
+	fn restore(&self, value: &mut Option<Arc<T>>, depth: usize) {
+		let current = self.depth.get();
+		self.depth.set(current - 1);
+		parameterization::count(false);
+		let restored = self.pending.get() == 0
+			&& match self.value.try_borrow_mut() {
+				Ok(mut slot) => {
+					mem::swap(&mut *slot, value);
+					true
+				}
+				Err(_) => false,
+			};
+		if restored {
+			self.shadowed.borrow_mut().pop();
+		} else {
+			// The value is borrowed by an enclosing `with`, which flushes when it returns or unwinds.
+			self.deferred.borrow_mut().push(value.take());
+			self.pending.set(self.pending.get() + 1);
+			self.flush();
+		}
+		debug_assert!(
+			current == depth || ::std::thread::panicking(),
+			"ParamGuard dropped out of order: guards of a parameter must be dropped in the \
+			 reverse order of their creation"
+		);
+	}
+
+	/// Applies the restores deferred while the value was borrowed, innermost first.
+	#[cold]
+	#[inline(never)]
+	fn flush(&self) {
+		let mut slot = match self.value.try_borrow_mut() {
+			Ok(slot) => slot,
+			Err(_) => return,
+		};
+		let deferred = mem::take(&mut *self.deferred.borrow_mut());
+		self.pending.set(0);
+		let displaced: Vec<_> = deferred.into_iter().map(|value| mem::replace(&mut *slot, value)).collect();
+		let popped = self.shadowed.borrow_mut().split_off(self.depth.get());
+		// Dropping the displaced values may read this parameter again.
+		drop(slot);
+		drop(displaced);
+		drop(popped);
+	}
+}
+
+/// Flushes the deferred restores of a slot once the borrow of its value has ended.
+struct Flush<'a, T: 'static>(&'a Slot<T>);
+
+impl<'a, T: 'static> Drop for Flush<'a, T> {
+	#[inline]
+	fn drop(&mut self) {
+		if self.0.pending.get() != 0 {
+			self.0.flush();
+		}
+	}
+}
+
+/// The binding primitives of every kind of parameter, through which `tramp!` binds them.
+#[doc(hidden)]
+pub trait __Bind {
+	type Value;
+	type Guard<'a>
+	where
+		Self: 'a;
+
+	fn __check(&self, value: Self::Value) -> Result<Self::Value, GuardError>;
+
+	/// Binds a value that has already been through `__check`.
+	fn __set_checked(&self, value: Self::Value) -> Result<Self::Guard<'_>, ParameterError>;
+}
+
+/// Generates the binding methods that every kind of parameter has from its `__Bind` primitives.
+///
+/// `$guard` is the type of the guard returned by `set_scoped`.
+macro_rules! binding_methods {
+	($t:ty, $guard:ty) => {
+		/// Runs `value` through the parameter's guard, if there is one, without binding it.
+		pub fn check(&self, value: $t) -> Result<$t, $crate::GuardError> {
+			$crate::__Bind::__check(self, value)
+		}
+
+		/// Binds the parameter to `value` on this thread for the duration of `f`.
+		///
+		/// The previous value is restored when `f` returns or unwinds.
+		///
+		/// # Panics
+		///
+		/// Panics if the parameter's guard rejects `value` or the binding cannot be installed; see
+		/// [`try_parameterize`](#method.try_parameterize).
+		pub fn parameterize<F, R>(&self, value: $t, f: F) -> R
+		where
+			F: FnOnce() -> R,
+		{
+			let _guard = self.set_scoped(value);
+			f()
+		}
+
+		/// Like [`parameterize`](#method.parameterize), but returns an error instead of panicking
+		/// when the parameter cannot be bound.
+		///
+		/// `f` is not run in that case.
+		pub fn try_parameterize<F, R>(&self, value: $t, f: F) -> Result<R, $crate::ParameterError>
+		where
+			F: FnOnce() -> R,
+		{
+			let _guard = self.try_set_scoped(value)?;
+			Ok(f())
+		}
+
+		/// Binds the parameter to `update` applied to its current value for the duration of `f`.
+		///
+		/// ```ignore
+		/// fn print(node: &Node) {
+		///     println!["{:indent$}{}", "", node.name, indent = INDENT.get()];
+		///     INDENT.update(|x| x + 4, || node.children.iter().for_each(print));
+		/// }
+		/// ```
+		///
+		/// # Panics
+		///
+		/// Panics if the current value cannot be read, or for the reasons
+		/// [`parameterize`](#method.parameterize) does.
+		pub fn update<U, F, R>(&self, update: U, f: F) -> R
+		where
+			U: FnOnce(&$t) -> $t,
+			F: FnOnce() -> R,
+		{
+			let value = self.with(update);
+			self.parameterize(value, f)
+		}
+
+		/// Binds the parameter to `value` on this thread until the returned guard is dropped.
+		///
+		/// This is for overrides that do not fit in a single block, such as a test fixture that sets
+		/// a parameter in its constructor and restores it when dropped. Guards of the same parameter
+		/// must be dropped in the reverse order of their creation; debug builds panic otherwise.
+		///
+		/// # Panics
+		///
+		/// Panics if the parameter's guard rejects `value` or the binding cannot be installed, for
+		/// instance because the value is borrowed by an enclosing `with` or the thread-local has
+		/// been destroyed.
+		pub fn set_scoped(&self, value: $t) -> $guard {
+			match self.try_set_scoped(value) {
+				Ok(guard) => guard,
+				Err(error) => panic!["{}", error],
+			}
+		}
+
+		/// Like [`set_scoped`](#method.set_scoped), but returns an error instead of panicking.
+		pub fn try_set_scoped(&self, value: $t) -> Result<$guard, $crate::ParameterError> {
+			let value = self.check(value)?;
+			$crate::__Bind::__set_checked(self, value)
+		}
+	};
+}
+
+/// A dynamically scoped variable, modeled on Racket's `make-parameter`.
+///
+/// The value lives in a thread-local [`Slot`](struct.Slot.html), so each thread starts out with
+/// the initial value and a parameterization only affects the thread that performs it.
+///
+/// Parameters are usually declared with the [`parameter!`](macro.parameter.html) macro.
+///
+/// ```ignore
+/// parameter! { static DEPTH: u32 = 0; }
+///
+/// DEPTH.parameterize(3, || assert_eq![DEPTH.get(), 3]);
+/// assert_eq![DEPTH.get(), 0];
+/// ```
+pub struct Parameter<T: 'static> {
+	name: &'static str,
+	key: &'static LocalKey<Slot<T>>,
+	guard: Option<Guard<T>>,
+}
+
+impl<T: 'static> Parameter<T> {
+	/// Creates a parameter named `name` backed by the given thread-local.
+	pub const fn new(name: &'static str, key: &'static LocalKey<Slot<T>>) -> Parameter<T> {
+		Parameter {
+			name,
+			key,
+			guard: None,
+		}
+	}
+
+	/// Checks and converts every new value of the parameter with `guard`.
+	pub const fn guard(self, guard: Guard<T>) -> Parameter<T> {
+		Parameter {
+			guard: Some(guard),
+			..self
+		}
+	}
+
+	/// The name the parameter was declared with.
+	pub fn name(&self) -> &'static str {
+		self.name
+	}
+
+	/// Returns a copy of the current value.
+	///
+	/// # Panics
+	///
+	/// Panics if the parameter is required and not bound, naming the parameter and the caller.
+	#[track_caller]
+	pub fn get(&self) -> T
+	where
+		T: Clone,
+	{
+		self.with(T::clone)
+	}
+
+	/// Returns a copy of the current value, or an error if it cannot be read.
+	pub fn try_get(&self) -> Result<T, ParameterError>
+	where
+		T: Clone,
+	{
+		self.try_with(T::clone)
+	}
+
+	/// Calls `f` with a reference to the current value.
+	///
+	/// # Panics
+	///
+	/// Panics if the value cannot be read, for instance because the parameter is required and not
+	/// bound, naming the parameter and the caller.
+	#[track_caller]
+	pub fn with<F, R>(&self, f: F) -> R
+	where
+		F: FnOnce(&T) -> R,
+	{
+		match self.try_with(f) {
+			Ok(value) => value,
+			Err(error) => panic!["{}, read at {}", error, Location::caller()],
+		}
+	}
+
+	/// Calls `f` with a reference to the current value, or returns an error if it cannot be read.
+	///
+	/// Besides an unbound required parameter, this reports reads during thread teardown, after the
+	/// parameter's thread-local has been destroyed.
+	pub fn try_with<F, R>(&self, f: F) -> Result<R, ParameterError>
+	where
+		F: FnOnce(&T) -> R,
+	{
+		let name = self.name;
+		self.key
+			.try_with(|x| {
+				// Declared first so it is dropped after the borrow, even when `f` unwinds.
+				let _flush = Flush(x);
+				let value = match x.value.try_borrow() {
+					Ok(value) => value,
+					Err(_) => return Err(ParameterError::Borrowed { name }),
+				};
+				match *value {
+					Some(ref value) => Ok(f(value)),
+					None => Err(ParameterError::Unbound { name }),
+				}
+			})
+			.unwrap_or(Err(ParameterError::Destroyed { name }))
+	}
+
+	/// Returns a copy of the value that the innermost binding on this thread shadows.
+	///
+	/// This is the value the parameter gets back when that binding exits, so a nested binding can
+	/// refer to the one around it:
+	///
+	/// ```ignore
+	/// tramp! { INDENT: 4 => assert_eq![INDENT.outer(), Some(0)] };
+	/// ```
+	///
+	/// Returns `None` if the parameter is not bound on this thread, or if it is required and was
+	/// unbound before the innermost binding.
+	pub fn outer(&self) -> Option<T>
+	where
+		T: Clone,
+	{
+		self.shadowed().and_then(|value| value.map(|value| T::clone(&value)))
+	}
+
+	/// Returns copies of the current value and of every value it shadows on this thread, innermost
+	/// first.
+	///
+	/// Unless the parameter is required, the last element is its initial value. This is meant for
+	/// debugging who bound what.
+	pub fn stack(&self) -> Vec<T>
+	where
+		T: Clone,
+	{
+		self.bindings().into_iter().flatten().map(|value| T::clone(&value)).collect()
+	}
+
+	/// Returns the value shadowed by the innermost binding, or `None` if there is no binding.
+	fn shadowed(&self) -> Option<Option<Arc<T>>> {
+		self.key.with(|x| x.shadowed.borrow().last().cloned())
+	}
+
+	/// Returns the current value followed by the values it shadows, innermost first.
+	fn bindings(&self) -> Vec<Option<Arc<T>>> {
+		self.key.with(|x| {
+			let mut values = vec![x.value.borrow().clone()];
+			values.extend(x.shadowed.borrow().iter().rev().cloned());
+			values
+		})
+	}
+
+	/// Returns the value bound on this thread, if any.
+	fn bound(&self) -> Result<Option<Arc<T>>, ParameterError> {
+		let name = self.name;
+		match self.key.try_with(|x| x.value.try_borrow().map(|value| value.clone())) {
+			Ok(Ok(value)) => Ok(value),
+			Ok(Err(_)) => Err(ParameterError::Borrowed { name }),
+			Err(_) => Err(ParameterError::Destroyed { name }),
+		}
+	}
+
+	binding_methods!(T, ParamGuard<'_, T>);
+}
+
+impl<T: 'static> __Bind for Parameter<T> {
+	type Value = T;
+	type Guard<'a> = ParamGuard<'a, T>;
+
+	fn __check(&self, value: T) -> Result<T, GuardError> {
+		match self.guard {
+			Some(guard) => guard(value).map_err(|error| GuardError::new(self.name, error)),
+			None => Ok(value),
+		}
+	}
+
+	fn __set_checked(&self, value: T) -> Result<ParamGuard<'_, T>, ParameterError> {
+		let name = self.name;
+		let mut old = Some(Arc::new(value));
+		let depth = self
+			.key
+			.try_with(|x| {
+				if !x.registered.replace(true) {
+					if let Some(register) = x.register {
+						register(self.key, name);
+					}
+				}
+				x.install(&mut old).map_err(|_| ParameterError::Borrowed { name })
+			})
+			.unwrap_or(Err(ParameterError::Destroyed { name }))?;
+		Ok(ParamGuard {
+			parameter: self,
+			old,
+			depth,
+			thread_bound: PhantomData,
+		})
+	}
+}
+
+/// Restores the previous value of a [`Parameter`](struct.Parameter.html) when dropped.
+///
+/// Created by [`Parameter::set_scoped`](struct.Parameter.html#method.set_scoped), which is also
+/// what `tramp!` uses to undo its bindings. A guard restores the value on the thread that created
+/// it, so it cannot be sent to another thread.
+///
+/// Dropping a guard never panics in release builds. If the value is still borrowed, for instance
+/// because the guard is dropped inside a [`with`](struct.Parameter.html#method.with) closure or
+/// while unwinding out of one, the restore is deferred until that borrow ends. If the thread-local
+/// is already destroyed, the failure is handled according to the
+/// [`RestorePolicy`](enum.RestorePolicy.html).
+#[must_use]
+pub struct ParamGuard<'a, T: 'static> {
+	parameter: &'a Parameter<T>,
+	old: Option<Arc<T>>,
+	depth: usize,
+	thread_bound: PhantomData<*const ()>,
+}
+
+impl<'a, T: 'static> Drop for ParamGuard<'a, T> {
+	fn drop(&mut self) {
+		let (old, depth) = (&mut self.old, self.depth);
+		if self.parameter.key.try_with(|x| x.restore(old, depth)).is_err() {
+			restore::failed(&ParameterError::Destroyed { name: self.parameter.name });
+		}
+	}
+}
+
+/// Declares one or more thread-local [`Parameter`](struct.Parameter.html)s.
+///
+/// The syntax mirrors `thread_local!`; attributes, doc comments and visibility are carried over
+/// to the generated `static`.
+///
+/// ```ignore
+/// parameter! {
+///     /// How much to log.
+///     pub static LOG_LEVEL: Level = Level::Info;
+///     static INDENT: usize = 0;
+///     local static OUTPUT: Box<dyn Write> = Box::new(io::stdout());
+/// }
+/// ```
+///
+/// Bindings of a parameter whose type is `Send` and `Sync` are inherited by threads spawned
+/// through [`thread::spawn`](thread/fn.spawn.html); bindings of any other type stay on the thread
+/// that made them. Declaring a parameter `local` opts out explicitly, whatever its type.
+/// Declaring it `global` makes a [`GlobalParameter`](struct.GlobalParameter.html) whose default
+/// is shared by all threads.
+///
+/// For `Copy` values read on hot paths, `cell static` makes a
+/// [`CellParameter`](struct.CellParameter.html) whose reads involve no borrow check, and
+/// `atomic static` makes an [`AtomicParameter`](struct.AtomicParameter.html), the lock-free
+/// counterpart of `global static` for primitive types. Both need a constant initial value.
+///
+/// A parameter declared without an initial value is required: it has no value outside a binding,
+/// so [`get`](struct.Parameter.html#method.get) panics and
+/// [`try_get`](struct.Parameter.html#method.try_get) returns
+/// [`ParameterError::Unbound`](enum.ParameterError.html) instead of silently using a placeholder.
+///
+/// ```ignore
+/// parameter! { pub static DB_HANDLE: Handle; }
+///
+/// tramp! { DB_HANDLE: connect()? => serve() };
+/// ```
+///
+/// A [`Guard`](type.Guard.html) that checks or converts every new value follows the initial value:
+///
+/// ```ignore
+/// parameter! {
+///     static PERCENT: u32 = 0, guard = |v| if v > 100 { Err("above 100".into()) } else { Ok(v) };
+/// }
+/// ```
+#[macro_export]
+macro_rules! parameter {
+	() => {};
+
+	(@declare $slot:ident ($($init:expr)?) [$($guard:expr)?] $(#[$attr:meta])* $vis:vis $name:ident : $t:ty) => {
+		$(#[$attr])*
+		$vis static $name: $crate::Parameter<$t> = {
+			::std::thread_local! {
+				static KEY: $crate::Slot<$t> = $crate::parameter![@slot $slot $t $(= $init)?];
+			}
+			$crate::Parameter::new(stringify!($name), &KEY) $(.guard($guard))?
+		};
+	};
+
+	(
+		$(#[$attr:meta])* $vis:vis static $name:ident : $t:ty = $init:expr $(, guard = $guard:expr)?
+		$(; $($rest:tt)*)?
+	) => {
+		$crate::parameter![@declare new ($init) [$($guard)?] $(#[$attr])* $vis $name : $t];
+		$crate::parameter![$($($rest)*)?];
+	};
+
+	(
+		$(#[$attr:meta])* $vis:vis static $name:ident : $t:ty $(, guard = $guard:expr)?
+		$(; $($rest:tt)*)?
+	) => {
+		$crate::parameter![@declare empty () [$($guard)?] $(#[$attr])* $vis $name : $t];
+		$crate::parameter![$($($rest)*)?];
+	};
+
+	(
+		$(#[$attr:meta])* $vis:vis local static $name:ident : $t:ty = $init:expr $(, guard = $guard:expr)?
+		$(; $($rest:tt)*)?
+	) => {
+		$crate::parameter![@declare local ($init) [$($guard)?] $(#[$attr])* $vis $name : $t];
+		$crate::parameter![$($($rest)*)?];
+	};
+
+	(
+		$(#[$attr:meta])* $vis:vis global static $name:ident : $t:ty = $init:expr $(, guard = $guard:expr)?
+		$(; $($rest:tt)*)?
+	) => {
+		$(#[$attr])*
+		$vis static $name: $crate::GlobalParameter<$t> = {
+			::std::thread_local! {
+				static KEY: $crate::Slot<$t> = $crate::Slot::empty();
+			}
+			$crate::GlobalParameter::new(
+				$crate::Parameter::new(stringify!($name), &KEY) $(.guard($guard))?,
+				|| $init,
+			)
+		};
+		$crate::parameter![$($($rest)*)?];
+	};
+
+	(
+		$(#[$attr:meta])* $vis:vis cell static $name:ident : $t:ty = $init:expr $(, guard = $guard:expr)?
+		$(; $($rest:tt)*)?
+	) => {
+		$(#[$attr])*
+		$vis static $name: $crate::CellParameter<$t> = {
+			::std::thread_local! {
+				static SHADOWED: ::std::cell::RefCell<::std::vec::Vec<$t>> =
+					const { ::std::cell::RefCell::new(::std::vec::Vec::new()) };
+				static KEY: $crate::CellSlot<$t> = const { $crate::CellSlot::new($init, &SHADOWED) };
+			}
+			$crate::CellParameter::new(stringify!($name), &KEY) $(.guard($guard))?
+		};
+		$crate::parameter![$($($rest)*)?];
+	};
+
+	(
+		$(#[$attr:meta])* $vis:vis atomic static $name:ident : $t:ty = $init:expr $(, guard = $guard:expr)?
+		$(; $($rest:tt)*)?
+	) => {
+		$(#[$attr])*
+		$vis static $name: $crate::AtomicParameter<$t> = {
+			::std::thread_local! {
+				static SHADOWED: ::std::cell::RefCell<::std::vec::Vec<::std::option::Option<$t>>> =
+					const { ::std::cell::RefCell::new(::std::vec::Vec::new()) };
+				static KEY: $crate::CellSlot<::std::option::Option<$t>> =
+					const { $crate::CellSlot::new(::std::option::Option::None, &SHADOWED) };
+			}
+			$crate::AtomicParameter::new(
+				$crate::CellParameter::new(stringify!($name), &KEY),
+				<<$t as $crate::Atomic>::Repr>::new($init),
+			) $(.guard($guard))?
+		};
+		$crate::parameter![$($($rest)*)?];
+	};
+
+	(@slot new $t:ty = $init:expr) => { {
+		#[allow(unused_imports)]
+		use $crate::{__Local, __Shared};
+		(&$crate::__Probe::<$t>(::std::marker::PhantomData)).__slot()($init)
+	} };
+
+	(@slot empty $t:ty) => { {
+		#[allow(unused_imports)]
+		use $crate::{__Local, __Shared};
+		(&$crate::__Probe::<$t>(::std::marker::PhantomData)).__empty()()
+	} };
+
+	(@slot local $t:ty = $init:expr) => {
+		$crate::Slot::local($init)
+	};
+}
+
+/// Picks the slot of a parameter declared by `parameter!`.
+///
+/// Only values that are `Send` and `Sync` may be captured and carried to another thread. The
+/// macro knows the concrete type, so method resolution can prefer [`__Shared`], which applies to
+/// such types, over [`__Local`], which applies to every type but only after an extra autoref.
+#[doc(hidden)]
+pub struct __Probe<T>(pub PhantomData<T>);
+
+#[doc(hidden)]
+pub trait __Shared<T: 'static> {
+	fn __slot(&self) -> fn(T) -> Slot<T>;
+	fn __empty(&self) -> fn() -> Slot<T>;
+}
+
+impl<T: Send + Sync + 'static> __Shared<T> for __Probe<T> {
+	fn __slot(&self) -> fn(T) -> Slot<T> {
+		Slot::new
+	}
+
+	fn __empty(&self) -> fn() -> Slot<T> {
+		Slot::empty
+	}
+}
+
+#[doc(hidden)]
+pub trait __Local<T: 'static> {
+	fn __slot(&self) -> fn(T) -> Slot<T>;
+	fn __empty(&self) -> fn() -> Slot<T>;
+}
+
+impl<T: 'static> __Local<T> for &__Probe<T> {
+	fn __slot(&self) -> fn(T) -> Slot<T> {
+		Slot::local
+	}
+
+	fn __empty(&self) -> fn() -> Slot<T> {
+		|| Slot::with_register(None, None)
+	}
+}
+
+/// Parameterizes one or more parameters for the duration of an expression.
+///
+/// The bindings are restored when the body exits, including by unwinding. The whole invocation
+/// evaluates to the value of the body. The body is expanded in place, so `return` and `?` leave
+/// the enclosing function as usual.
+///
+/// Binding is transactional: every value expression is evaluated first, from left to right and
+/// seeing the outer values of all parameters, then every guard checks its value, and only then
+/// are the bindings installed. If an expression panics or a guard rejects its value, no parameter
+/// has been changed.
+///
+/// Parameters may be named by path, a trailing comma is allowed after the last binding and the
+/// body can be any expression:
+///
+/// ```ignore
+/// let x = tramp! { A: 1, config::VERBOSE: true, => compute() };
+/// ```
+///
+/// A binding can be skipped at run time, so one body serves both cases. `X: ?value` binds `X`
+/// only if the `Option` `value` is `Some`, and `X: value if condition` only if `condition` holds,
+/// in which case alone `value` is evaluated. The two combine as `X: ?value if condition`:
+///
+/// ```ignore
+/// tramp! { LOG_LEVEL: ?args.log_level, VERBOSE: true if args.verbose => run() }
+/// ```
+///
+/// A binding can also be relative to the parameter's current value, which is read with `get`.
+/// `DEPTH += 1` is short for `DEPTH: DEPTH.get() + 1`, and `-=` works likewise:
+///
+/// ```ignore
+/// fn descend(node: &Node) {
+///     tramp! { DEPTH += 1 => node.children.iter().for_each(descend) }
+/// }
+/// ```
+///
+/// # Panic safety
+///
+/// If the body, a value expression or a parameter's guard panics, every binding this invocation
+/// already installed is restored while the panic unwinds, innermost first. Code that catches the
+/// panic with `catch_unwind`, at whatever nesting depth, therefore sees each parameter at the value
+/// of its own enclosing binding, and later bindings work as usual. Restoring never panics itself
+/// (see [`ParamGuard`](struct.ParamGuard.html)), so a panic inside `tramp!` cannot become an
+/// abort. The same holds for `try_tramp!`, the `parameterize` methods and
+/// [`Parameterization::enter`](struct.Parameterization.html#method.enter).
+///
+/// Malformed invocations are reported with a `compile_error!`:
+///
+/// ```compile_fail
+/// # #[macro_use] extern crate parameterize;
+/// # parameter! { static A: u32 = 0; }
+/// # fn main() {
+/// tramp! { A: 1 };
+/// # }
+/// ```
+///
+/// ```compile_fail
+/// # #[macro_use] extern crate parameterize;
+/// # fn main() {
+/// tramp! { => 1 };
+/// # }
+/// ```
+///
+/// ```compile_fail
+/// # #[macro_use] extern crate parameterize;
+/// # parameter! { static A: u32 = 0; }
+/// # fn main() {
+/// tramp! { A: 1 if => () };
+/// # }
+/// ```
+///
+/// ```compile_fail
+/// # #[macro_use] extern crate parameterize;
+/// # parameter! { static A: u32 = 0; }
+/// # fn main() {
+/// tramp! { A += => () };
+/// # }
+/// ```
 #[macro_export]
 macro_rules! tramp {
-	($ih:ident : $eh:expr, $($it:ident : $et:expr),* => $b:block) => { {
-			let old = $ih.with(|x| {
-				let old = x.borrow().clone();
-				*x.borrow_mut() = $eh;
-				old
-			});
-			defer![$ih.with(|x| { *x.borrow_mut() = old.clone(); })];
-			tramp![$($it : $et),* => $b];
-		}
-	};
-
-	($ih:ident : $eh:expr => $b:block) => {
-		let old = $ih.with(|x| {
-			let old = x.borrow().clone();
-			*x.borrow_mut() = $eh;
-			old
-		});
-		{
-			defer![$ih.with(|x| { *x.borrow_mut() = old.clone(); })];
-			$b
+	(@parse $mode:ident [] => $($body:tt)*) => {
+		compile_error!(concat!(
+			stringify!($mode),
+			"!: expected at least one `PARAMETER: value` binding before `=>`"
+		))
+	};
+
+	(@parse $mode:ident [$($bound:tt)*] => { $($block:tt)* }) => {
+		$crate::tramp![@$mode [$($bound)*] { $($block)* }]
+	};
+
+	(@parse $mode:ident [$($bound:tt)*] => $body:expr) => {
+		$crate::tramp![@$mode [$($bound)*] { $body }]
+	};
+
+	(@parse $mode:ident [$($bound:tt)*] => $($body:tt)*) => {
+		compile_error!(concat!(stringify!($mode), "!: expected a single expression after `=>`"))
+	};
+
+	// Every binding is collected as `{PARAMETER, value}`, where `value` is an `Option` that is
+	// `None` when the binding is skipped.
+	(@parse $mode:ident [$($bound:tt)*] $p:path : ? $e:expr, $($rest:tt)*) => {
+		$crate::tramp![@parse $mode [$($bound)* {$p, $e}] $($rest)*]
+	};
+
+	(@parse $mode:ident [$($bound:tt)*] $p:path : ? $e:expr => $($rest:tt)*) => {
+		$crate::tramp![@parse $mode [$($bound)* {$p, $e}] => $($rest)*]
+	};
+
+	(@parse $mode:ident [$($bound:tt)*] $p:path : $e:expr, $($rest:tt)*) => {
+		$crate::tramp![@parse $mode [$($bound)* {$p, ::std::option::Option::Some($e)}] $($rest)*]
+	};
+
+	(@parse $mode:ident [$($bound:tt)*] $p:path : $e:expr => $($rest:tt)*) => {
+		$crate::tramp![@parse $mode [$($bound)* {$p, ::std::option::Option::Some($e)}] => $($rest)*]
+	};
+
+	(@parse $mode:ident [$($bound:tt)*] $p:path : $e:expr) => {
+		compile_error!(concat!(
+			stringify!($mode),
+			"!: expected `=>` followed by an expression after the last binding"
+		))
+	};
+
+	// An `expr` fragment cannot be followed by `if`, so a conditional binding is split by hand.
+	(@parse $mode:ident [$($bound:tt)*] $p:path : $($rest:tt)*) => {
+		$crate::tramp![@value $mode [$($bound)*] $p [] $($rest)*]
+	};
+
+	(@parse $mode:ident [$($bound:tt)*] $($p:ident)::+ += $($rest:tt)*) => {
+		$crate::tramp![@update $mode [$($bound)*] [$($p)::+] += $($rest)*]
+	};
+
+	(@parse $mode:ident [$($bound:tt)*] $($p:ident)::+ -= $($rest:tt)*) => {
+		$crate::tramp![@update $mode [$($bound)*] [$($p)::+] -= $($rest)*]
+	};
+
+	(@parse $mode:ident [$($bound:tt)*] $($rest:tt)*) => {
+		compile_error!(concat!(
+			stringify!($mode),
+			"!: expected bindings of the form `PARAMETER: value` or `PARAMETER += value`, separated by \
+			 commas"
+		))
+	};
+
+	(@value $mode:ident [$($bound:tt)*] $p:path [$v:tt $($value:tt)*] if $($rest:tt)*) => {
+		$crate::tramp![@condition $mode [$($bound)*] $p [$v $($value)*] [] $($rest)*]
+	};
+
+	(@value $mode:ident [$($bound:tt)*] $p:path [$($value:tt)*] => $($rest:tt)*) => {
+		compile_error!(concat!(
+			stringify!($mode),
+			"!: expected a value of the form `expression`, `?expression` or `value if condition`"
+		))
+	};
+
+	(@value $mode:ident [$($bound:tt)*] $p:path [$($value:tt)*] $t:tt $($rest:tt)*) => {
+		$crate::tramp![@value $mode [$($bound)*] $p [$($value)* $t] $($rest)*]
+	};
+
+	(@value $mode:ident [$($bound:tt)*] $p:path [$($value:tt)*]) => {
+		compile_error!(concat!(
+			stringify!($mode),
+			"!: expected `=>` followed by an expression after the last binding"
+		))
+	};
+
+	(@condition $mode:ident [$($bound:tt)*] $p:path [$($value:tt)*] [] , $($rest:tt)*) => {
+		compile_error!(concat!(stringify!($mode), "!: expected a condition after `if`"))
+	};
+
+	(@condition $mode:ident [$($bound:tt)*] $p:path [$($value:tt)*] [] => $($rest:tt)*) => {
+		compile_error!(concat!(stringify!($mode), "!: expected a condition after `if`"))
+	};
+
+	(@condition $mode:ident [$($bound:tt)*] $p:path [$($value:tt)*] [$($c:tt)+] , $($rest:tt)*) => {
+		$crate::tramp![@parse $mode [$($bound)* {$p, $crate::tramp![@when [$($c)+] $($value)*]}] $($rest)*]
+	};
+
+	(@condition $mode:ident [$($bound:tt)*] $p:path [$($value:tt)*] [$($c:tt)+] => $($rest:tt)*) => {
+		$crate::tramp![@parse $mode [$($bound)* {$p, $crate::tramp![@when [$($c)+] $($value)*]}] => $($rest)*]
+	};
+
+	(@condition $mode:ident [$($bound:tt)*] $p:path [$($value:tt)*] [$($c:tt)*] $t:tt $($rest:tt)*) => {
+		$crate::tramp![@condition $mode [$($bound)*] $p [$($value)*] [$($c)* $t] $($rest)*]
+	};
+
+	(@condition $mode:ident [$($bound:tt)*] $p:path [$($value:tt)*] [$($c:tt)*]) => {
+		compile_error!(concat!(
+			stringify!($mode),
+			"!: expected `=>` followed by an expression after the last binding"
+		))
+	};
+
+	(@when [$($c:tt)+] ? $($value:tt)+) => {
+		if $($c)+ { $($value)+ } else { ::std::option::Option::None }
+	};
+
+	(@when [$($c:tt)+] $($value:tt)+) => {
+		if $($c)+ { ::std::option::Option::Some($($value)+) } else { ::std::option::Option::None }
+	};
+
+	(@update $mode:ident [$($bound:tt)*] [$($p:tt)*] $op:tt , $($rest:tt)*) => {
+		compile_error!(concat!(stringify!($mode), "!: expected a value after `", stringify!($op), "`"))
+	};
+
+	(@update $mode:ident [$($bound:tt)*] [$($p:tt)*] $op:tt => $($rest:tt)*) => {
+		compile_error!(concat!(stringify!($mode), "!: expected a value after `", stringify!($op), "`"))
+	};
+
+	// `P += e` binds `P` to its current value plus `e`, using the operator on a copy of the value.
+	(@update $mode:ident [$($bound:tt)*] [$($p:tt)*] $op:tt $e:expr, $($rest:tt)*) => {
+		$crate::tramp![@parse $mode [$($bound)* {$($p)*, ::std::option::Option::Some({
+			let mut value = $($p)*.get();
+			value $op $e;
+			value
+		})}] $($rest)*]
+	};
+
+	(@update $mode:ident [$($bound:tt)*] [$($p:tt)*] $op:tt $e:expr => $($rest:tt)*) => {
+		$crate::tramp![@parse $mode [$($bound)* {$($p)*, ::std::option::Option::Some({
+			let mut value = $($p)*.get();
+			value $op $e;
+			value
+		})}] => $($rest)*]
+	};
+
+	(@update $mode:ident [$($bound:tt)*] [$($p:tt)*] $op:tt $($rest:tt)*) => {
+		compile_error!(concat!(
+			stringify!($mode),
+			"!: expected `=>` followed by an expression after the last binding"
+		))
+	};
+
+	(@tramp [$($bound:tt)*] $body:tt) => {
+		$crate::tramp![@eval tramp [$($bound)*] [] $body]
+	};
+
+	(@try_tramp [$($bound:tt)*] $body:tt) => {
+		$crate::tramp![@eval try_tramp [$($bound)*] [] $body]
+	};
+
+	// Each value is evaluated in its own expansion, which gives every `value` a distinct name.
+	(@eval $mode:ident [{$p:path, $e:expr} $($rest:tt)*] [$($done:tt)*] $body:tt) => { {
+		let value = $e;
+		$crate::tramp![@eval $mode [$($rest)*] [$($done)* {$p, value}] $body]
+	} };
+
+	(@eval tramp [] [$({$p:path, $v:ident})*] { $($body:tt)* }) => { {
+		$crate::tramp![@install [$({$p, $v})*]];
+		$($body)*
+	} };
+
+	(@eval try_tramp [] [$($bound:tt)*] $body:tt) => {
+		$crate::tramp![@try_check [$($bound)*] [$($bound)*] $body]
+	};
+
+	// Every guard runs before the first binding is installed.
+	(@install [$({$p:path, $v:ident})*]) => {
+		$(
+			let $v = match $v {
+				::std::option::Option::Some(value) => match $crate::__Bind::__check(&$p, value) {
+					::std::result::Result::Ok(value) => ::std::option::Option::Some(value),
+					::std::result::Result::Err(error) => ::std::panic!("{}", error),
+				},
+				::std::option::Option::None => ::std::option::Option::None,
+			};
+		)*
+		$(
+			let _guard = match $v {
+				::std::option::Option::Some(value) => match $crate::__Bind::__set_checked(&$p, value) {
+					::std::result::Result::Ok(guard) => ::std::option::Option::Some(guard),
+					::std::result::Result::Err(error) => ::std::panic!("{}", error),
+				},
+				::std::option::Option::None => ::std::option::Option::None,
+			};
+		)*
+	};
+
+	// `try_tramp!` nests a `match` per step rather than breaking out of a labeled block, so the
+	// body keeps the `break` and `continue` of the caller's loops.
+	(@try_check [{$p:path, $v:ident} $($rest:tt)*] [$($bound:tt)*] $body:tt) => {
+		match $v.map(|value| $crate::__Bind::__check(&$p, value)).transpose() {
+			::std::result::Result::Ok($v) => $crate::tramp![@try_check [$($rest)*] [$($bound)*] $body],
+			::std::result::Result::Err(error) => ::std::result::Result::Err($crate::ParameterError::from(error)),
+		}
+	};
+
+	(@try_check [] [$($bound:tt)*] $body:tt) => {
+		$crate::tramp![@try_install [$($bound)*] $body]
+	};
+
+	(@try_install [{$p:path, $v:ident} $($rest:tt)*] $body:tt) => {
+		match $v.map(|value| $crate::__Bind::__set_checked(&$p, value)).transpose() {
+			::std::result::Result::Ok(_guard) => $crate::tramp![@try_install [$($rest)*] $body],
+			::std::result::Result::Err(error) => ::std::result::Result::Err($crate::ParameterError::from(error)),
 		}
 	};
+
+	(@try_install [] { $($body:tt)* }) => {
+		::std::result::Result::Ok({ $($body)* })
+	};
+
+	($($input:tt)*) => {
+		$crate::tramp![@parse tramp [] $($input)*]
+	};
 }
 
+/// Like [`tramp!`](macro.tramp.html), but returns an error instead of panicking when a binding
+/// cannot be installed.
+///
+/// The invocation evaluates to `Ok` with the value of the body, or to `Err` with the
+/// [`ParameterError`](enum.ParameterError.html) of the first binding that failed, usually because
+/// its guard rejected the value. In that case the body is not run and no parameter is left
+/// changed.
+///
+/// ```ignore
+/// match try_tramp! { LIMIT: requested => run() } {
+///     Ok(x) => x,
+///     Err(error) => return Err(error.into()),
+/// }
+/// ```
+#[macro_export]
+macro_rules! try_tramp {
+	($($input:tt)*) => {
+		$crate::tramp![@parse try_tramp [] $($input)*]
+	};
+}
+
+mod atomic;
+mod bindings;
+mod cell;
+mod error;
+mod global;
+pub mod future;
+pub mod iter;
+mod parameterization;
+mod restore;
+pub mod thread;
+
+pub use atomic::{Atomic, AtomicParameter};
+pub use bindings::{Bindable, Bindings};
+pub use cell::{CellGuard, CellParameter, CellSlot};
+pub use error::{Guard, GuardError, ParameterError};
+pub use global::GlobalParameter;
+pub use parameterization::{bind, bind_mut, bind_once, Parameterization};
+pub use restore::{restore_policy, set_restore_policy, RestorePolicy};
+pub use thread::scope;
+
 #[cfg(test)]
 mod tests {
 
+	use super::{ParamGuard, ParameterError};
 	use std::cell::RefCell;
+	use std::panic;
+	use std::rc::Rc;
 
-	thread_local! {
-		pub static FOO: RefCell<u32> = RefCell::new(0);
-		pub static BAR: RefCell<String> = RefCell::new(String::new());
+	parameter! {
+		static FOO: u32 = 0;
+		static BAR: String = String::new();
+		static BAZ: Vec<u8> = Vec::new();
+		static LOG: Box<dyn Fn(&str)> = Box::new(|_| ());
+		static HANDLE: u32;
+		static AFTER_FOO: u32 = 0, guard = |v| if FOO.get() == 0 { Ok(v) } else { Err("FOO is bound".into()) };
+		static PERCENT: u32 = 0, guard = |v| if v > 100 { Err("above 100".into()) } else { Ok(v) };
+		static TRIMMED: String = String::new(), guard = |v: String| Ok(v.trim().to_string())
+	}
+
+	mod config {
+		parameter! {
+			/// Whether to print progress.
+			pub static VERBOSE: bool = false;
+			pub(crate) static NAME: &'static str = "config"
+		}
 	}
 
 	#[test]
 	fn single_variable() {
 
 		tramp! { FOO: 100 => {
-			FOO.with(|x| {
-			BAR.with(|y| {
-				assert_eq![*x.borrow(), 100];
-				assert_eq![*y.borrow(), ""];
-			})});
+			assert_eq![FOO.get(), 100];
+			assert_eq![BAR.get(), ""];
 		}}
 
-		FOO.with(|x| {
-		BAR.with(|y| {
-			assert_eq![*x.borrow(), 0];
-			assert_eq![*y.borrow(), ""];
-		})});
+		assert_eq![FOO.get(), 0];
+		assert_eq![BAR.get(), ""];
 
 	}
 
@@ -95,18 +1075,510 @@ mod tests {
 	fn two_variables() {
 
 		tramp! { FOO: 1, BAR: "A".to_string() => {
-			FOO.with(|x| {
-			BAR.with(|y| {
-				assert_eq![*x.borrow(), 1];
-				assert_eq![*y.borrow(), "A"];
-			})});
+			assert_eq![FOO.get(), 1];
+			assert_eq![BAR.get(), "A"];
+		}}
+
+		assert_eq![FOO.get(), 0];
+		assert_eq![BAR.get(), ""];
+	}
+
+	#[test]
+	fn value_of_one_binding() {
+
+		let x = tramp! { FOO: 3 => { FOO.get() * 2 } };
+
+		assert_eq![x, 6];
+		assert_eq![FOO.get(), 0];
+	}
+
+	#[test]
+	fn value_of_two_bindings() {
+
+		let x = tramp! { FOO: 3, BAR: "abc".to_string() => {
+			BAR.with(|y| y.len() as u32) + FOO.get()
+		}};
+
+		assert_eq![x, 6];
+		assert_eq![FOO.get(), 0];
+		assert_eq![BAR.get(), ""];
+	}
+
+	#[test]
+	fn value_of_many_bindings() {
+
+		let x = tramp! { FOO: 1, BAR: "b".to_string(), BAZ: vec![1, 2, 3] => {
+			format!["{}{}{:?}", FOO.get(), BAR.get(), BAZ.get()]
+		}};
+
+		assert_eq![x, "1b[1, 2, 3]"];
+		assert_eq![FOO.get(), 0];
+		assert_eq![BAR.get(), ""];
+		assert![BAZ.get().is_empty()];
+	}
+
+	#[test]
+	fn expression_body() {
+
+		assert_eq![tramp! { FOO: 4 => FOO.get() + 1 }, 5];
+		assert_eq![tramp! { FOO: 4, BAR: "x".to_string() => BAR.get() }, "x"];
+	}
+
+	#[test]
+	fn body_keeps_the_expected_type() {
+
+		let (x, f): (u32, fn() -> u32) = tramp! { FOO: 4 => (FOO.get(), || 1) };
+		let y: (u32, fn() -> u32) = tramp! { FOO: 4 => { (FOO.get(), || 2) } };
+
+		assert_eq![x + f() + y.0 + (y.1)(), 11];
+	}
+
+	#[test]
+	fn trailing_comma() {
+
+		assert_eq![tramp! { FOO: 4, => FOO.get() }, 4];
+		assert_eq![tramp! { FOO: 4, BAR: "x".to_string(), => FOO.get() }, 4];
+	}
+
+	#[test]
+	fn path_qualified_parameter() {
+
+		tramp! { config::VERBOSE: true, self::FOO: 2, super::tests::BAR: "y".to_string() => {
+			assert![config::VERBOSE.get()];
+			assert_eq![FOO.get(), 2];
+			assert_eq![BAR.get(), "y"];
+		}}
+
+		assert![!config::VERBOSE.get()];
+	}
+
+	#[test]
+	fn non_clone_value() {
+
+		let lines = Rc::new(RefCell::new(Vec::new()));
+		let sink = lines.clone();
+
+		tramp! { LOG: Box::new(move |x: &str| sink.borrow_mut().push(x.to_string())) => {
+			LOG.with(|log| log("inside"));
+		}}
+
+		LOG.with(|log| log("outside"));
+
+		assert_eq![*lines.borrow(), ["inside"]];
+		assert_eq![Rc::strong_count(&lines), 1];
+	}
+
+	#[test]
+	fn question_mark_in_body() {
+
+		fn parse(text: &str) -> Result<u32, ::std::num::ParseIntError> {
+			tramp! { FOO: 1 => Ok(text.parse::<u32>()? + FOO.get()) }
+		}
+
+		assert_eq![parse("2"), Ok(3)];
+		assert![parse("x").is_err()];
+		assert_eq![FOO.get(), 0];
+	}
+
+	#[test]
+	fn scoped_guard() {
+
+		struct Fixture {
+			_foo: ParamGuard<'static, u32>,
+			_bar: ParamGuard<'static, String>,
+		}
+
+		fn setup() -> Fixture {
+			Fixture {
+				_foo: FOO.set_scoped(5),
+				_bar: BAR.set_scoped("fixture".to_string()),
+			}
+		}
+
+		let fixture = setup();
+		assert_eq![FOO.get(), 5];
+		assert_eq![BAR.get(), "fixture"];
+
+		{
+			let _inner = FOO.set_scoped(6);
+			assert_eq![FOO.get(), 6];
+		}
+
+		assert_eq![FOO.get(), 5];
+		drop(fixture);
+		assert_eq![FOO.get(), 0];
+		assert_eq![BAR.get(), ""];
+	}
+
+	#[test]
+	fn guards_of_different_parameters_in_any_order() {
+
+		let foo = FOO.set_scoped(1);
+		let bar = BAR.set_scoped("x".to_string());
+		drop(foo);
+		drop(bar);
+
+		assert_eq![FOO.get(), 0];
+		assert_eq![BAR.get(), ""];
+	}
+
+	#[test]
+	#[cfg(debug_assertions)]
+	#[should_panic(expected = "ParamGuard dropped out of order")]
+	fn guards_dropped_out_of_order() {
+
+		let outer = FOO.set_scoped(1);
+		let _inner = FOO.set_scoped(2);
+		drop(outer);
+	}
+
+	#[test]
+	fn guard_accepts_and_converts() {
+
+		tramp! { PERCENT: 100, TRIMMED: "  padded ".to_string() => {
+			assert_eq![PERCENT.get(), 100];
+			assert_eq![TRIMMED.get(), "padded"];
 		}}
+	}
+
+	#[test]
+	#[should_panic(expected = "invalid value for parameter `PERCENT`: above 100")]
+	fn guard_rejects_in_tramp() {
+
+		tramp! { PERCENT: 101 => () }
+	}
+
+	#[test]
+	fn try_tramp_returns_the_value() {
+
+		let x = try_tramp! { FOO: 1, PERCENT: 50 => FOO.get() + PERCENT.get() };
+
+		assert_eq![x.unwrap(), 51];
+	}
+
+	#[test]
+	fn try_tramp_returns_the_guard_error() {
+
+		let mut entered = false;
+		let x = try_tramp! { FOO: 1, PERCENT: 150, BAR: "x".to_string() => {
+			entered = true;
+		}};
+
+		let error = x.unwrap_err();
+		assert_eq![error.parameter(), "PERCENT"];
+		assert_eq![error.to_string(), "invalid value for parameter `PERCENT`: above 100"];
+		assert![!entered];
+		assert_eq![FOO.get(), 0];
+		assert_eq![PERCENT.get(), 0];
+	}
+
+	#[test]
+	fn try_tramp_body_can_break_and_continue() {
+
+		let mut seen = Vec::new();
+		for i in 0..5 {
+			let x = try_tramp! { FOO: i, PERCENT: 10 => {
+				if i == 1 {
+					continue;
+				}
+				if i == 3 {
+					break;
+				}
+				FOO.get() + PERCENT.get()
+			}};
+			seen.push(x.unwrap());
+		}
+
+		assert_eq![seen, [10, 12]];
+		assert_eq![(FOO.get(), PERCENT.get()), (0, 0)];
+	}
+
+	#[test]
+	fn values_are_evaluated_before_any_binding() {
+
+		let x = tramp! { FOO: 1, BAR: FOO.get().to_string(), BAZ: vec![FOO.get() as u8] => {
+			format!["{}{}{:?}", FOO.get(), BAR.get(), BAZ.get()]
+		}};
+
+		assert_eq![x, "10[0]"];
+	}
+
+	#[test]
+	fn guards_run_before_any_binding() {
+
+		assert_eq![tramp! { FOO: 1, AFTER_FOO: 2 => AFTER_FOO.get() + FOO.get() }, 3];
+		assert![tramp! { FOO: 1 => try_tramp! { AFTER_FOO: 2 => () } }.is_err()];
+	}
+
+	#[test]
+	fn failed_binding_changes_nothing() {
+
+		let mut evaluated = Vec::new();
+		let x = try_tramp! {
+			FOO: { evaluated.push(FOO.get()); 1 },
+			PERCENT: { evaluated.push(FOO.get()); 150 },
+			BAR: { evaluated.push(FOO.get()); "x".to_string() },
+			=> ()
+		};
+
+		assert_eq![x.unwrap_err().parameter(), "PERCENT"];
+		assert_eq![evaluated, [0, 0, 0]];
+		assert_eq![FOO.get(), 0];
+	}
+
+	#[test]
+	fn relative_update() {
+
+		fn descend(levels: u32) -> u32 {
+			if levels == 0 {
+				FOO.get()
+			} else {
+				tramp! { FOO += 1 => descend(levels - 1) }
+			}
+		}
+
+		assert_eq![descend(5), 5];
+		assert_eq![tramp! { FOO: 10 => descend(2) }, 12];
+
+		let x = tramp! { FOO: 10, BAR: "a".to_string() => {
+			tramp! { self::FOO -= 3, BAR += "b", PERCENT += 5, => (FOO.get(), BAR.get(), PERCENT.get()) }
+		}};
+
+		assert_eq![x, (7, "ab".to_string(), 5)];
+		assert![try_tramp! { PERCENT: 100 => try_tramp! { PERCENT += 1 => () } }.unwrap().is_err()];
+		assert_eq![FOO.get(), 0];
+	}
+
+	#[test]
+	fn optional_binding() {
+
+		let some = Some(3);
+		let none: Option<u32> = None;
+
+		assert_eq![tramp! { FOO: ?some => FOO.get() }, 3];
+		assert_eq![tramp! { FOO: ?none, BAR: ?Some("x".to_string()) => (FOO.get(), BAR.get()) }, (0, "x".to_string())];
+		assert_eq![tramp! { FOO: 1 => tramp! { FOO: ?none => FOO.get() } }, 1];
+		assert![try_tramp! { PERCENT: ?None => () }.is_ok()];
+		assert![try_tramp! { PERCENT: ?Some(101) => () }.is_err()];
+	}
+
+	#[test]
+	fn conditional_binding() {
+
+		let mut evaluated = 0;
+		let mut value = || {
+			evaluated += 1;
+			7
+		};
+
+		assert_eq![tramp! { FOO: value() if false => FOO.get() }, 0];
+		assert_eq![tramp! { FOO: value() if 1 < 2, BAR: "x".to_string() if true => FOO.get() }, 7];
+		assert_eq![evaluated, 1];
+
+		let flag = true;
+		let x = tramp! { config::VERBOSE: !flag if flag, self::FOO: ?Some(2) if !flag, => {
+			(config::VERBOSE.get(), FOO.get())
+		}};
+
+		assert_eq![x, (false, 0)];
+		assert![try_tramp! { PERCENT: 101 if false => () }.is_ok()];
+		assert![try_tramp! { PERCENT: 101 if true => () }.is_err()];
+		assert_eq![tramp! { FOO: if flag { 4 } else { 5 } if flag => FOO.get() }, 4];
+	}
+
+	#[test]
+	fn update_method() {
+
+		let x = FOO.update(|x| x + 2, || FOO.update(|x| x * 10, || FOO.get()));
+
+		assert_eq![x, 20];
+		assert_eq![BAR.update(|x| format!["{}!", x], || BAR.get()), "!"];
+		assert_eq![FOO.get(), 0];
+	}
+
+	#[test]
+	fn try_set_scoped() {
+
+		assert![PERCENT.try_set_scoped(1000).is_err()];
+
+		let guard = PERCENT.try_set_scoped(10).unwrap();
+		assert_eq![PERCENT.get(), 10];
+		drop(guard);
+		assert_eq![PERCENT.get(), 0];
+	}
+
+	#[test]
+	fn parameterize_method() {
+
+		let length = FOO.parameterize(7, || {
+			BAR.parameterize("seven".to_string(), || {
+				assert_eq![FOO.get(), 7];
+				BAR.with(|x| x.len())
+			})
+		});
+
+		assert_eq![length, 5];
+		assert_eq![FOO.get(), 0];
+		assert_eq![BAR.get(), ""];
+	}
+
+	#[test]
+	fn required_parameter() {
+
+		match HANDLE.try_get() {
+			Err(ParameterError::Unbound { name }) => assert_eq![name, "HANDLE"],
+			other => panic!["unexpected {:?}", other],
+		}
+
+		tramp! { HANDLE: 3 => {
+			assert_eq![HANDLE.get(), 3];
+			assert_eq![HANDLE.try_get().unwrap(), 3];
+		}}
+
+		assert![HANDLE.try_get().is_err()];
+	}
+
+	#[test]
+	#[should_panic(expected = "parameter `HANDLE` is required but not bound, read at src/lib.rs:")]
+	fn required_parameter_read_outside_a_binding() {
+
+		HANDLE.get();
+	}
+
+	#[test]
+	fn rebinding_a_borrowed_parameter() {
+
+		FOO.with(|_| {
+			match FOO.try_parameterize(1, || ()) {
+				Err(ParameterError::Borrowed { name }) => assert_eq![name, "FOO"],
+				other => panic!["unexpected {:?}", other],
+			}
+			assert![try_tramp! { BAR: "x".to_string(), FOO: 1 => () }.is_err()];
+			assert_eq![BAR.try_get().unwrap(), ""];
+		});
+
+		assert_eq![FOO.try_parameterize(2, || FOO.get()).unwrap(), 2];
+	}
+
+	#[test]
+	#[should_panic(expected = "parameter `FOO` cannot be rebound while its value is borrowed")]
+	fn set_scoped_on_a_borrowed_parameter() {
+
+		FOO.with(|_| drop(FOO.set_scoped(1)));
+	}
+
+	#[test]
+	fn restore_is_deferred_while_borrowed() {
+
+		let outer = FOO.set_scoped(1);
+		let inner = FOO.set_scoped(2);
 
 		FOO.with(|x| {
-		BAR.with(|y| {
-			assert_eq![*x.borrow(), 0];
-			assert_eq![*y.borrow(), ""];
-		})});
+			drop(inner);
+			drop(outer);
+			assert_eq![*x, 2];
+		});
+
+		assert_eq![FOO.get(), 0];
+	}
+
+	#[test]
+	fn outer_and_stack() {
+
+		assert_eq![FOO.outer(), None];
+		assert_eq![FOO.stack(), [0]];
+
+		tramp! { FOO: 1 => tramp! { FOO: 2, BAR: "x".to_string() => {
+			assert_eq![FOO.outer(), Some(1)];
+			assert_eq![FOO.stack(), [2, 1, 0]];
+			assert_eq![BAR.outer(), Some(String::new())];
+			FOO.update(|x| x * 10, || assert_eq![FOO.stack(), [20, 2, 1, 0]]);
+		}}}
+
+		assert_eq![tramp! { HANDLE: 1 => tramp! { HANDLE: 2 => (HANDLE.outer(), HANDLE.stack()) } }, (Some(1), vec![2, 1])];
+		assert_eq![tramp! { HANDLE: 1 => HANDLE.outer() }, None];
+		assert![HANDLE.stack().is_empty()];
+	}
+
+	#[test]
+	fn outer_while_a_restore_is_deferred() {
+
+		let outer = FOO.set_scoped(1);
+		let inner = FOO.set_scoped(2);
+
+		FOO.with(|_| {
+			drop(inner);
+			assert_eq![FOO.stack(), [2, 1, 0]];
+		});
+
+		assert_eq![FOO.stack(), [1, 0]];
+		drop(outer);
+		assert_eq![FOO.outer(), None];
+	}
+
+	#[test]
+	fn restore_while_unwinding_out_of_a_borrow() {
+
+		let guard = FOO.set_scoped(1);
+
+		let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+			FOO.with(move |_| {
+				let _guard = guard;
+				panic!["body"];
+			})
+		}));
+
+		assert![result.is_err()];
+		assert_eq![FOO.get(), 0];
+		assert_eq![tramp! { FOO: 3 => FOO.get() }, 3];
+	}
+
+	#[test]
+	fn read_after_thread_local_destruction() {
+
+		use std::sync::mpsc;
+
+		/// Reads `TEARDOWN` when dropped. Bound to `TEARDOWN` itself, it is dropped while that
+		/// thread-local is being destroyed, whatever order the thread-locals are destroyed in.
+		struct Teardown(Option<mpsc::Sender<Result<(), ParameterError>>>);
+
+		impl Drop for Teardown {
+			fn drop(&mut self) {
+				if let Some(sender) = self.0.take() {
+					let _ = sender.send(TEARDOWN.try_with(|_| ()));
+				}
+			}
+		}
+
+		parameter! {
+			static TEARDOWN: Teardown = Teardown(None);
+		}
+
+		let (sender, receiver) = mpsc::channel();
+		::std::thread::spawn(move || {
+			// The binding is never undone, so it is still installed when the thread exits.
+			::std::mem::forget(TEARDOWN.set_scoped(Teardown(Some(sender))));
+		})
+		.join()
+		.unwrap();
+
+		match receiver.recv().unwrap() {
+			Err(ParameterError::Destroyed { name }) => assert_eq![name, "TEARDOWN"],
+			other => panic!["unexpected {:?}", other],
+		}
+	}
+
+	#[test]
+	fn declared_in_module() {
+
+		assert![!config::VERBOSE.get()];
+		assert_eq![config::NAME.get(), "config"];
+
+		config::VERBOSE.parameterize(true, || {
+			assert![config::VERBOSE.get()];
+		});
+
+		assert![!config::VERBOSE.get()];
 	}
 
 }
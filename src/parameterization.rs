@@ -0,0 +1,412 @@
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::sync::Arc;
+use std::thread::LocalKey;
+
+use restore;
+use {ParameterError, Slot};
+
+pub(crate) type Shared = Arc<dyn Any + Send + Sync>;
+
+thread_local! {
+	static REGISTERED: RefCell<Vec<Key>> = const { RefCell::new(Vec::new()) };
+	// The number of bindings installed on this thread, of parameters of every kind.
+	static INSTALLED: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Returns the number of bindings installed on this thread.
+pub(crate) fn installed() -> usize {
+	INSTALLED.with(Cell::get)
+}
+
+/// Counts a binding as installed, or as restored if `installed` is false.
+pub(crate) fn count(installed: bool) {
+	INSTALLED.with(|x| x.set(if installed { x.get() + 1 } else { x.get() - 1 }));
+}
+
+/// A parameter's slot with the value type erased, along with the parameter's name for errors.
+#[derive(Clone, Copy)]
+pub(crate) struct Key {
+	pub(crate) binding: &'static dyn Binding,
+	pub(crate) name: &'static str,
+}
+
+impl Key {
+	fn same(self, other: Key) -> bool {
+		let address = |key: Key| key.binding as *const dyn Binding as *const ();
+		address(self) == address(other)
+	}
+}
+
+/// Records that a parameter has been bound on this thread, so later captures consider it.
+///
+/// Nothing is recorded once the thread's thread-locals are being destroyed.
+pub(crate) fn register<K: Binding>(key: &'static K, name: &'static str) {
+	let _ = REGISTERED.try_with(|x| x.borrow_mut().push(Key { binding: key, name }));
+}
+
+fn registered() -> Vec<Key> {
+	REGISTERED.try_with(|x| x.borrow().clone()).unwrap_or_default()
+}
+
+/// A parameter's slot with the value type erased.
+pub(crate) trait Binding: Sync {
+	/// Returns the current value if the parameter is bound on this thread, and `None` once its
+	/// thread-local is destroyed.
+	fn capture(&'static self) -> Option<Shared>;
+
+	/// Installs `value`, or the initial value if `None`, returning what must be passed to
+	/// `restore`. `name` is the parameter's, for registering it and for errors.
+	fn install(
+		&'static self,
+		name: &'static str,
+		value: Option<&Shared>,
+	) -> Result<(Option<Shared>, usize), ParameterError>;
+
+	fn restore(&'static self, old: Option<Shared>, depth: usize);
+}
+
+impl<T: Send + Sync + 'static> Binding for LocalKey<Slot<T>> {
+	fn capture(&'static self) -> Option<Shared> {
+		self.try_with(|x| {
+			if x.depth.get() > 0 {
+				x.value.borrow().clone().map(|value| value as Shared)
+			} else {
+				None
+			}
+		})
+		.unwrap_or(None)
+	}
+
+	fn install(
+		&'static self,
+		name: &'static str,
+		value: Option<&Shared>,
+	) -> Result<(Option<Shared>, usize), ParameterError> {
+		self.try_with(|x| {
+			let mut value = match value {
+				Some(value) => Some(value.clone().downcast::<T>().expect("captured value has the wrong type")),
+				None => x.base.clone(),
+			};
+			if !x.registered.replace(true) {
+				if let Some(register) = x.register {
+					register(self, name);
+				}
+			}
+			let depth = x.install(&mut value).map_err(|_| ParameterError::Borrowed { name })?;
+			Ok((value.map(|value| value as Shared), depth))
+		})
+		.unwrap_or(Err(ParameterError::Destroyed { name }))
+	}
+
+	fn restore(&'static self, old: Option<Shared>, depth: usize) {
+		let mut old = old.map(|old| old.downcast::<T>().expect("saved value has the wrong type"));
+		if self.try_with(|x| x.restore(&mut old, depth)).is_err() {
+			restore::failed(&"a thread-local was destroyed before its parameterization exited");
+		}
+	}
+}
+
+/// A snapshot of the parameter bindings of a thread, like Racket's `current-parameterization`.
+///
+/// Capturing is cheap: the bound values are reference counted rather than copied. Entering the
+/// snapshot later runs a closure under exactly the captured bindings, which is what callbacks
+/// that fire long after their `tramp!` scope has ended need. A snapshot can be sent to another
+/// thread and entered there. Parameters declared `local` are neither captured nor replaced.
+///
+/// ```ignore
+/// let snapshot = tramp! { VERBOSE: true => Parameterization::current() };
+/// assert![!VERBOSE.get()];
+/// snapshot.enter(|| assert![VERBOSE.get()]);
+/// ```
+#[derive(Clone)]
+pub struct Parameterization {
+	values: Vec<(Key, Shared)>,
+}
+
+impl Parameterization {
+	/// Captures the bindings of the current thread.
+	pub fn current() -> Parameterization {
+		Parameterization {
+			values: registered()
+				.into_iter()
+				.filter_map(|key| key.binding.capture().map(|value| (key, value)))
+				.collect(),
+		}
+	}
+
+	/// Runs `f` with exactly the captured bindings installed, like Racket's
+	/// `call-with-parameterization`.
+	///
+	/// Parameters that were unbound when the snapshot was captured see their initial value, even if
+	/// they are bound by the caller. The caller's bindings are restored when `f` returns or unwinds.
+	///
+	/// # Panics
+	///
+	/// Panics if a binding cannot be installed, because the parameter's value is borrowed by an
+	/// enclosing `with` or its thread-local has been destroyed. The adaptors built on this, such as
+	/// [`bind`](fn.bind.html) and `with_parameters`, panic likewise. Use
+	/// [`try_enter`](#method.try_enter) to handle these cases.
+	pub fn enter<F, R>(&self, f: F) -> R
+	where
+		F: FnOnce() -> R,
+	{
+		match self.try_enter(f) {
+			Ok(value) => value,
+			Err(error) => panic!["{}", error],
+		}
+	}
+
+	/// Like [`enter`](#method.enter), but returns an error instead of panicking when a binding
+	/// cannot be installed.
+	///
+	/// `f` is not run in that case, and the bindings installed so far are restored.
+	pub fn try_enter<F, R>(&self, f: F) -> Result<R, ParameterError>
+	where
+		F: FnOnce() -> R,
+	{
+		let mut entered = Entered::new();
+		for &(key, ref value) in &self.values {
+			entered.install(key, Some(value))?;
+		}
+		for key in registered() {
+			if key.binding.capture().is_some() && !self.values.iter().any(|&(x, _)| x.same(key)) {
+				entered.install(key, None)?;
+			}
+		}
+		Ok(f())
+	}
+}
+
+/// Returns a closure that runs `f` under the bindings that are current now.
+///
+/// This is the dynamic-scope counterpart of a closure capturing its environment: wherever and
+/// whenever the result is called, such as from an event loop long after the surrounding `tramp!`
+/// has exited, `f` sees the parameters as they were at the call to `bind`. The closure is `Send`
+/// whenever `f` is.
+///
+/// Calling the closure panics where
+/// [`Parameterization::enter`](struct.Parameterization.html#method.enter) would, such as inside a
+/// `with` of one of the captured parameters.
+///
+/// ```ignore
+/// let on_click = tramp! { THEME: Theme::Dark => parameterize::bind(|| render()) };
+/// event_loop.register(on_click);
+/// ```
+pub fn bind<F, R>(f: F) -> impl Fn() -> R
+where
+	F: Fn() -> R,
+{
+	let parameterization = Parameterization::current();
+	move || parameterization.enter(&f)
+}
+
+/// Like [`bind`](fn.bind.html), for closures that mutate their state.
+pub fn bind_mut<F, R>(mut f: F) -> impl FnMut() -> R
+where
+	F: FnMut() -> R,
+{
+	let parameterization = Parameterization::current();
+	move || parameterization.enter(&mut f)
+}
+
+/// Like [`bind`](fn.bind.html), for closures that are called once.
+pub fn bind_once<F, R>(f: F) -> impl FnOnce() -> R
+where
+	F: FnOnce() -> R,
+{
+	let parameterization = Parameterization::current();
+	move || parameterization.enter(f)
+}
+
+/// Restores the bindings it installed when dropped, in reverse order.
+pub(crate) struct Entered {
+	saved: Vec<(&'static dyn Binding, Option<Shared>, usize)>,
+}
+
+impl Entered {
+	pub(crate) fn new() -> Entered {
+		Entered { saved: Vec::new() }
+	}
+
+	/// Installs `value`, or the initial value if `None`, until `self` is dropped.
+	pub(crate) fn install(&mut self, key: Key, value: Option<&Shared>) -> Result<(), ParameterError> {
+		let (old, depth) = key.binding.install(key.name, value)?;
+		self.saved.push((key.binding, old, depth));
+		Ok(())
+	}
+}
+
+impl Drop for Entered {
+	fn drop(&mut self) {
+		while let Some((key, old, depth)) = self.saved.pop() {
+			key.restore(old, depth);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::{bind, bind_mut, bind_once, Parameterization};
+	use std::panic;
+	use std::sync::mpsc;
+	use std::thread;
+	use ParameterError;
+
+	parameter! {
+		static FOO: u32 = 0;
+		static BAR: String = String::new();
+	}
+
+	#[test]
+	fn callback_after_scope() {
+
+		let callbacks: Vec<(Parameterization, fn() -> u32)> = vec![
+			tramp! { FOO: 1 => (Parameterization::current(), || FOO.get()) },
+			tramp! { FOO: 2 => (Parameterization::current(), || FOO.get() * 10) },
+		];
+
+		let results: Vec<u32> = callbacks.iter().map(|&(ref snapshot, f)| snapshot.enter(f)).collect();
+
+		assert_eq![results, [1, 20]];
+		assert_eq![FOO.get(), 0];
+	}
+
+	#[test]
+	fn exactly_the_captured_bindings() {
+
+		let snapshot = tramp! { BAR: "captured".to_string() => Parameterization::current() };
+
+		tramp! { FOO: 5, BAR: "caller".to_string() => {
+			snapshot.enter(|| {
+				assert_eq![FOO.get(), 0];
+				assert_eq![BAR.get(), "captured"];
+			});
+			assert_eq![FOO.get(), 5];
+			assert_eq![BAR.get(), "caller"];
+		}}
+	}
+
+	#[test]
+	fn nested_bindings_inside_enter() {
+
+		let snapshot = tramp! { FOO: 1 => Parameterization::current() };
+
+		let x = snapshot.enter(|| tramp! { FOO: FOO.get() + 1 => FOO.get() });
+
+		assert_eq![x, 2];
+		assert_eq![FOO.get(), 0];
+	}
+
+	#[test]
+	fn restored_after_panic() {
+
+		let snapshot = tramp! { FOO: 1 => Parameterization::current() };
+
+		let result = panic::catch_unwind(panic::AssertUnwindSafe(|| snapshot.enter(|| panic!["inside"])));
+
+		assert![result.is_err()];
+		assert_eq![FOO.get(), 0];
+	}
+
+	#[test]
+	fn send_and_sync() {
+
+		fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+		assert_send_sync(&tramp! { BAR: "x".to_string() => Parameterization::current() });
+	}
+
+	#[test]
+	fn empty_parameterization() {
+
+		let snapshot = Parameterization::current();
+
+		tramp! { FOO: 3 => snapshot.enter(|| assert_eq![FOO.get(), 0]) };
+	}
+
+	#[test]
+	fn bound_closure_called_after_scope() {
+
+		let callbacks: Vec<Box<dyn Fn() -> String>> = vec![
+			tramp! { FOO: 1, BAR: "a".to_string() => Box::new(bind(|| format!["{}{}", BAR.get(), FOO.get()])) },
+			tramp! { FOO: 2 => Box::new(bind(|| format!["{}{}", BAR.get(), FOO.get()])) },
+		];
+
+		let results: Vec<String> = tramp! { BAR: "caller".to_string() => callbacks.iter().map(|f| f()).collect() };
+
+		assert_eq![results, ["a1", "2"]];
+		assert_eq![callbacks[0](), "a1"];
+		assert_eq![FOO.get(), 0];
+	}
+
+	#[test]
+	fn bound_closure_sent_to_another_thread() {
+
+		let f = tramp! { FOO: 7 => bind(|| FOO.get()) };
+
+		assert_eq![thread::spawn(f).join().unwrap(), 7];
+	}
+
+	#[test]
+	fn bound_mut_and_once() {
+
+		let mut seen = Vec::new();
+		let mut record = tramp! { FOO: 3 => bind_mut(|| seen.push(FOO.get())) };
+		record();
+		tramp! { FOO: 4 => record() };
+		drop(record);
+
+		let name = "once".to_string();
+		let take = tramp! { BAR: "bound".to_string() => bind_once(move || name + &BAR.get()) };
+
+		assert_eq![seen, [3, 3]];
+		assert_eq![take(), "oncebound"];
+	}
+
+	#[test]
+	fn try_enter_inside_with() {
+
+		let snapshot = tramp! { BAR: "captured".to_string(), FOO: 1 => Parameterization::current() };
+
+		FOO.with(|_| match snapshot.try_enter(|| panic!["not run"]) {
+			Err(ParameterError::Borrowed { name }) => {
+				assert_eq![name, "FOO"];
+				assert_eq![BAR.get(), ""];
+			}
+			other => panic!["unexpected {:?}", other],
+		});
+		assert_eq![FOO.get(), 0];
+	}
+
+	#[test]
+	fn capture_during_teardown() {
+
+		/// Captures and enters the bindings when dropped, which for a bound value is while its
+		/// parameter's thread-local is destroyed.
+		struct Capture(Option<mpsc::Sender<bool>>);
+
+		impl Drop for Capture {
+			fn drop(&mut self) {
+				if let Some(sender) = self.0.take() {
+					let _ = sender.send(Parameterization::current().try_enter(|| ()).is_ok());
+				}
+			}
+		}
+
+		parameter! {
+			static CAPTURE: Capture = Capture(None);
+		}
+
+		let (sender, receiver) = mpsc::channel();
+		thread::spawn(move || {
+			// The binding is never undone, so it is still installed when the thread exits.
+			::std::mem::forget(CAPTURE.set_scoped(Capture(Some(sender))));
+		})
+		.join()
+		.unwrap();
+
+		assert![receiver.recv().unwrap()];
+	}
+}
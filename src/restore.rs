@@ -0,0 +1,146 @@
+//! What happens when a binding cannot be undone.
+//!
+//! A binding is restored by a destructor, often while unwinding from a panic, so a failure there
+//! must not panic again. A restore that finds the value borrowed is deferred until the borrow is
+//! released. The only restores that cannot happen at all are those attempted after the thread's
+//! thread-locals have been destroyed, while the thread exits; they are handled according to the
+//! [`RestorePolicy`](enum.RestorePolicy.html).
+
+use std::fmt;
+use std::process;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// What to do when a binding cannot be restored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RestorePolicy {
+	/// Print the failure to standard error and abort the process.
+	Abort,
+	/// Print the failure to standard error and carry on. This is the default.
+	Log,
+	/// Carry on silently.
+	Ignore,
+}
+
+static POLICY: AtomicU8 = AtomicU8::new(RestorePolicy::Log as u8);
+
+/// Sets the [`RestorePolicy`](enum.RestorePolicy.html) of the whole process.
+pub fn set_restore_policy(policy: RestorePolicy) {
+	POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+/// Returns the current [`RestorePolicy`](enum.RestorePolicy.html).
+pub fn restore_policy() -> RestorePolicy {
+	match POLICY.load(Ordering::Relaxed) {
+		0 => RestorePolicy::Abort,
+		1 => RestorePolicy::Log,
+		_ => RestorePolicy::Ignore,
+	}
+}
+
+/// Reports a restore that could not happen. Never panics.
+pub(crate) fn failed(error: &dyn fmt::Display) {
+	match restore_policy() {
+		RestorePolicy::Abort => {
+			eprintln!["parameterize: could not restore a binding: {}; aborting", error];
+			process::abort();
+		}
+		RestorePolicy::Log => eprintln!["parameterize: could not restore a binding: {}", error],
+		RestorePolicy::Ignore => {}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::{restore_policy, set_restore_policy, RestorePolicy};
+	use std::cell::RefCell;
+	use std::env;
+	use std::process::{Command, Output};
+	use std::sync::mpsc;
+	use std::thread;
+
+	/// Holds a guard of `HOLDER`, and reports on the channel once the guard has been dropped.
+	struct Holder(RefCell<Option<::ParamGuard<'static, Holder>>>, Option<mpsc::Sender<()>>);
+
+	impl Drop for Holder {
+		fn drop(&mut self) {
+			self.0.borrow_mut().take();
+			if let Some(sender) = self.1.take() {
+				let _ = sender.send(());
+			}
+		}
+	}
+
+	parameter! {
+		static HOLDER: Holder = Holder(RefCell::new(None), None);
+	}
+
+	/// Drops a guard of `HOLDER` while `HOLDER` itself is being destroyed.
+	fn restore_after_teardown() {
+
+		let (sender, receiver) = mpsc::channel();
+		thread::spawn(move || {
+			// The bound value keeps its own guard, so the binding is undone only when the value is
+			// dropped along with the thread-local, whatever order the thread-locals are destroyed in.
+			let guard = HOLDER.set_scoped(Holder(RefCell::new(None), Some(sender)));
+			HOLDER.with(|x| *x.0.borrow_mut() = Some(guard));
+		})
+		.join()
+		.unwrap();
+
+		receiver.recv().unwrap();
+	}
+
+	#[test]
+	fn policy_round_trips() {
+
+		// Other tests in this process rely on restores not aborting.
+		for &policy in &[RestorePolicy::Ignore, RestorePolicy::Log] {
+			set_restore_policy(policy);
+			assert_eq![restore_policy(), policy];
+		}
+	}
+
+	/// Runs the test `name` alone in a child process, which sees `PARAMETERIZE_RESTORE_CHILD`.
+	fn run_in_child(name: &str) -> Output {
+		Command::new(env::current_exe().unwrap())
+			.args(["--exact", name, "--test-threads=1", "--nocapture"])
+			.env("PARAMETERIZE_RESTORE_CHILD", "1")
+			.output()
+			.unwrap()
+	}
+
+	#[test]
+	fn log_policy() {
+
+		if env::var_os("PARAMETERIZE_RESTORE_CHILD").is_some() {
+			set_restore_policy(RestorePolicy::Log);
+			restore_after_teardown();
+			return;
+		}
+
+		let output = run_in_child("restore::tests::log_policy");
+
+		let stderr = String::from_utf8_lossy(&output.stderr);
+		assert![output.status.success(), "{}", stderr];
+		assert![stderr.contains("could not restore a binding: parameter `HOLDER` was used after"), "{}", stderr];
+		assert![!stderr.contains("aborting"), "{}", stderr];
+	}
+
+	#[test]
+	fn abort_policy() {
+
+		if env::var_os("PARAMETERIZE_RESTORE_CHILD").is_some() {
+			set_restore_policy(RestorePolicy::Abort);
+			restore_after_teardown();
+			return;
+		}
+
+		let output = run_in_child("restore::tests::abort_policy");
+
+		assert![!output.status.success()];
+		let stderr = String::from_utf8_lossy(&output.stderr);
+		assert![stderr.contains("could not restore a binding: parameter `HOLDER` was used after"), "{}", stderr];
+		assert![stderr.contains("aborting"), "{}", stderr];
+	}
+}
@@ -0,0 +1,214 @@
+//! Spawning threads that inherit the caller's parameter bindings.
+//!
+//! A thread started with `std::thread::spawn` sees the initial value of every parameter, no matter
+//! which `tramp!` blocks surround the call. The functions here capture the current
+//! [`Parameterization`](../struct.Parameterization.html) and enter it in the new thread, so the
+//! child starts out with the same bindings as its parent. Parameters declared `local` are not
+//! inherited.
+//!
+//! ```ignore
+//! tramp! { VERBOSE: true => {
+//!     parameterize::thread::spawn(|| assert![VERBOSE.get()]).join().unwrap();
+//! }}
+//! ```
+
+use std::io;
+use std::thread::{self, Builder, JoinHandle, ScopedJoinHandle};
+
+use parameterization::Parameterization;
+
+/// Spawns a thread that runs `f` under the caller's current bindings.
+///
+/// This is `std::thread::spawn` with the bindings carried over.
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+	F: FnOnce() -> T + Send + 'static,
+	T: Send + 'static,
+{
+	let parameterization = Parameterization::current();
+	thread::spawn(move || parameterization.enter(f))
+}
+
+/// Adds [`spawn_with_parameters`](#tymethod.spawn_with_parameters) to `std::thread::Builder`.
+pub trait BuilderExt {
+	/// Spawns a thread configured by this builder that runs `f` under the caller's current
+	/// bindings.
+	fn spawn_with_parameters<F, T>(self, f: F) -> io::Result<JoinHandle<T>>
+	where
+		F: FnOnce() -> T + Send + 'static,
+		T: Send + 'static;
+}
+
+impl BuilderExt for Builder {
+	fn spawn_with_parameters<F, T>(self, f: F) -> io::Result<JoinHandle<T>>
+	where
+		F: FnOnce() -> T + Send + 'static,
+		T: Send + 'static,
+	{
+		let parameterization = Parameterization::current();
+		self.spawn(move || parameterization.enter(f))
+	}
+}
+
+/// Creates a scope for spawning threads that inherit the caller's bindings.
+///
+/// This mirrors `std::thread::scope`: the threads may borrow from the enclosing stack frame, and
+/// all of them are joined before `scope` returns.
+///
+/// Only the bindings are carried over, and parameters hold `'static` values, so a borrowed value
+/// cannot be passed to the workers by binding it. Borrow it in the worker's closure instead, as
+/// with `std::thread::scope`, or bind an owned or reference-counted value.
+///
+/// ```ignore
+/// let totals = tramp! { SCALE: 10 => parameterize::scope(|s| {
+///     let workers: Vec<_> = chunks.iter().map(|c| s.spawn(move || c.len() * SCALE.get())).collect();
+///     workers.into_iter().map(|w| w.join().unwrap()).collect::<Vec<_>>()
+/// })};
+/// ```
+pub fn scope<'env, F, T>(f: F) -> T
+where
+	F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+{
+	thread::scope(|scope| f(Scope::wrap(scope)))
+}
+
+/// A scope for spawning threads that inherit the bindings, created by [`scope`](fn.scope.html).
+#[repr(transparent)]
+pub struct Scope<'scope, 'env: 'scope> {
+	scope: thread::Scope<'scope, 'env>,
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+	fn wrap(scope: &'scope thread::Scope<'scope, 'env>) -> &'scope Scope<'scope, 'env> {
+		// `Scope` is a transparent wrapper, so the two references have the same layout.
+		unsafe { &*(scope as *const thread::Scope<'scope, 'env> as *const Scope<'scope, 'env>) }
+	}
+
+	/// Spawns a scoped thread that runs `f` under the bindings current at the time of the call.
+	///
+	/// The bindings are captured on every call, so a thread spawned from within a worker inherits
+	/// that worker's bindings.
+	pub fn spawn<F, T>(&'scope self, f: F) -> ScopedJoinHandle<'scope, T>
+	where
+		F: FnOnce() -> T + Send + 'scope,
+		T: Send + 'scope,
+	{
+		let parameterization = Parameterization::current();
+		self.scope.spawn(move || parameterization.enter(f))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::{scope, spawn, BuilderExt};
+	use std::cell::Cell;
+	use std::thread::{self, Builder};
+
+	parameter! {
+		static FOO: u32 = 0;
+		static BAR: String = String::new();
+		local static LOCAL: Cell<u32> = Cell::new(0);
+		static UNSYNC: Cell<u32> = Cell::new(0);
+	}
+
+	#[test]
+	fn child_inherits_bindings() {
+
+		let x = tramp! { FOO: 1, BAR: "parent".to_string() => {
+			spawn(|| format!["{}{}", FOO.get(), BAR.get()]).join().unwrap()
+		}};
+
+		assert_eq![x, "1parent"];
+	}
+
+	#[test]
+	fn std_spawn_does_not_inherit() {
+
+		let x = tramp! { FOO: 1 => thread::spawn(|| FOO.get()).join().unwrap() };
+
+		assert_eq![x, 0];
+	}
+
+	#[test]
+	fn builder_inherits_bindings() {
+
+		let handle = tramp! { FOO: 2 => {
+			Builder::new()
+				.name("child".to_string())
+				.spawn_with_parameters(|| (thread::current().name().map(String::from), FOO.get()))
+				.unwrap()
+		}};
+
+		assert_eq![handle.join().unwrap(), (Some("child".to_string()), 2)];
+	}
+
+	#[test]
+	fn local_parameters_are_not_inherited() {
+
+		let x = tramp! { FOO: 3, LOCAL: Cell::new(3) => {
+			spawn(|| (FOO.get(), LOCAL.with(Cell::get))).join().unwrap()
+		}};
+
+		assert_eq![x, (3, 0)];
+	}
+
+	#[test]
+	fn parameters_that_are_not_sync_stay_on_their_thread() {
+
+		let x = tramp! { FOO: 4, UNSYNC: Cell::new(4) => {
+			spawn(|| (FOO.get(), UNSYNC.with(Cell::get))).join().unwrap()
+		}};
+
+		assert_eq![x, (4, 0)];
+	}
+
+	#[test]
+	fn grandchild_inherits_the_childs_bindings() {
+
+		let x = tramp! { FOO: 1, BAR: "parent".to_string() => {
+			spawn(|| {
+				tramp! { FOO: FOO.get() + 1 => {
+					spawn(|| format!["{}{}", FOO.get(), BAR.get()]).join().unwrap()
+				}}
+			}).join().unwrap()
+		}};
+
+		assert_eq![x, "2parent"];
+	}
+
+	#[test]
+	fn scoped_workers_inherit_bindings() {
+
+		let words = ["a".to_string(), "bb".to_string(), "ccc".to_string()];
+
+		let lengths = tramp! { FOO: 10 => scope(|s| {
+			let workers: Vec<_> = words.iter().map(|w| s.spawn(move || w.len() as u32 * FOO.get())).collect();
+			workers.into_iter().map(|w| w.join().unwrap()).collect::<Vec<_>>()
+		})};
+
+		assert_eq![lengths, [10, 20, 30]];
+		assert_eq![words.len(), 3];
+	}
+
+	#[test]
+	fn nested_scoped_workers() {
+
+		let x = tramp! { FOO: 1, BAR: "scope".to_string() => scope(|s| {
+			s.spawn(move || {
+				tramp! { FOO: 2 => s.spawn(|| format!["{}{}", FOO.get(), BAR.get()]) }
+			}).join().unwrap().join().unwrap()
+		})};
+
+		assert_eq![x, "2scope"];
+	}
+
+	#[test]
+	fn bindings_after_scope_is_entered_are_inherited() {
+
+		let x = scope(|s| tramp! { FOO: 4 => s.spawn(|| FOO.get()).join().unwrap() });
+
+		assert_eq![x, 4];
+		assert_eq![FOO.get(), 0];
+	}
+}
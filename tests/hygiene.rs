@@ -0,0 +1,53 @@
+//! `tramp!` and `parameter!` must expand correctly in a crate that imports nothing else.
+
+extern crate parameterize;
+
+use parameterize::{parameter, tramp, try_tramp};
+
+parameter! {
+	static DEPTH: u32 = 0;
+	static NAME: String = String::new();
+	static HANDLE: u32;
+	static SMALL: u8 = 0, guard = |v| if v < 10 { Ok(v) } else { Err("too big".into()) };
+}
+
+#[test]
+fn single_binding() {
+	assert_eq![tramp! { DEPTH: 1 => DEPTH.get() }, 1];
+	assert_eq![DEPTH.get(), 0];
+}
+
+#[test]
+fn multiple_bindings() {
+	let x = tramp! { DEPTH: 1, NAME: "a".to_string(), => {
+		tramp! { DEPTH: DEPTH.get() + 1 => format!["{}{}", NAME.get(), DEPTH.get()] }
+	}};
+
+	assert_eq![x, "a2"];
+	assert_eq![DEPTH.get(), 0];
+	assert_eq![NAME.get(), ""];
+}
+
+#[test]
+fn try_tramp() {
+	assert_eq![try_tramp! { DEPTH: 1, SMALL: 2 => DEPTH.get() + SMALL.get() as u32 }.unwrap(), 3];
+	assert![try_tramp! { SMALL: 20 => () }.is_err()];
+}
+
+#[test]
+fn required_parameter() {
+	assert![HANDLE.try_get().is_err()];
+	assert_eq![tramp! { HANDLE: 1 => HANDLE.get() }, 1];
+}
+
+#[test]
+fn relative_update() {
+	assert_eq![tramp! { DEPTH: 1 => tramp! { DEPTH += 2, NAME += "x" => DEPTH.get() } }, 3];
+}
+
+#[test]
+fn conditional_bindings() {
+	let depth = Some(4);
+	assert_eq![tramp! { DEPTH: ?depth, NAME: "x".to_string() if depth.is_none() => DEPTH.get() }, 4];
+	assert_eq![NAME.get(), ""];
+}
@@ -0,0 +1,143 @@
+//! After a panic, every parameter must be back at the value of its enclosing binding, wherever
+//! the panic started and wherever it is caught.
+
+extern crate parameterize;
+
+use std::panic::{self, AssertUnwindSafe};
+
+use parameterize::{parameter, tramp, try_tramp, Parameterization};
+
+parameter! {
+	static DEPTH: u32 = 0;
+	static NAME: String = String::new();
+	static LIST: Vec<u32> = Vec::new();
+	static CHECKED: u32 = 0, guard = |v| if v == 13 { panic!["unlucky"] } else { Ok(v) };
+	cell static FLAG: bool = false;
+	global static LIMIT: u32 = 10;
+	atomic static COUNT: usize = 0;
+}
+
+fn catch<R, F: FnOnce() -> R>(f: F) -> Option<R> {
+	panic::catch_unwind(AssertUnwindSafe(f)).ok()
+}
+
+/// Evaluates to any type, panicking instead; stands in for a value expression that fails.
+fn fail<T>(message: &str) -> T {
+	panic!["{}", message]
+}
+
+fn assert_unbound() {
+	assert_eq![DEPTH.get(), 0];
+	assert_eq![NAME.get(), ""];
+	assert![LIST.get().is_empty()];
+	assert_eq![CHECKED.get(), 0];
+	assert![!FLAG.get()];
+	assert_eq![LIMIT.get(), 10];
+	assert_eq![COUNT.get(), 0];
+}
+
+/// Binds `DEPTH` to each level down to `depth` and panics at the bottom.
+fn descend(level: u32, depth: u32) {
+	tramp! { DEPTH: level, NAME: format!["level {}", level] => {
+		if level == depth {
+			panic!["bottom"];
+		}
+		descend(level + 1, depth);
+	}}
+}
+
+#[test]
+fn panic_in_the_body() {
+	assert![catch(|| tramp! { DEPTH: 1 => panic!["body"] }).is_none()];
+	assert_unbound();
+}
+
+#[test]
+fn panic_at_any_depth_caught_at_the_top() {
+	for depth in 1..10 {
+		assert![catch(|| descend(1, depth)).is_none()];
+		assert_unbound();
+	}
+}
+
+#[test]
+fn panic_caught_at_every_intermediate_depth() {
+	for catch_at in 1..5 {
+		tramp! { DEPTH: catch_at, NAME: "catcher".to_string() => {
+			assert![catch(|| descend(catch_at + 1, 8)).is_none()];
+			assert_eq![DEPTH.get(), catch_at];
+			assert_eq![NAME.get(), "catcher"];
+		}}
+		assert_unbound();
+	}
+}
+
+#[test]
+fn panic_with_several_bindings_in_the_body() {
+	let result = catch(|| {
+		tramp! { DEPTH: 1, NAME: "a".to_string(), LIST: vec![1], FLAG: true, LIMIT: 1, COUNT: 1 => {
+			panic!["body"]
+		}}
+	});
+
+	assert![result.is_none()];
+	assert_unbound();
+}
+
+#[test]
+fn panic_between_the_first_and_second_binding() {
+	let result = catch(|| {
+		tramp! { DEPTH: 1, NAME: fail("value"), LIST: vec![1] => () }
+	});
+
+	assert![result.is_none()];
+	assert_unbound();
+}
+
+#[test]
+fn panic_in_the_guard_of_a_later_binding() {
+	assert![catch(|| tramp! { DEPTH: 1, FLAG: true, CHECKED: 13 => () }).is_none()];
+	assert_unbound();
+
+	assert![catch(|| try_tramp! { DEPTH: 1, CHECKED: 13 => () }).is_none()];
+	assert_unbound();
+}
+
+#[test]
+fn panic_inside_nested_bindings_of_the_same_parameter() {
+	tramp! { DEPTH: 1 => {
+		let result = catch(|| {
+			tramp! { DEPTH: 2 => tramp! { DEPTH: 3, DEPTH: 4 => panic!["inner"] } }
+		});
+		assert![result.is_none()];
+		assert_eq![DEPTH.get(), 1];
+	}}
+	assert_unbound();
+}
+
+#[test]
+fn panic_in_methods() {
+	assert![catch(|| DEPTH.parameterize(1, || NAME.parameterize("x".to_string(), || panic!["x"]))).is_none()];
+	assert![catch(|| FLAG.parameterize(true, || panic!["flag"])).is_none()];
+	assert![catch(|| LIMIT.parameterize(1, || COUNT.parameterize(1, || panic!["limit"]))).is_none()];
+	assert_unbound();
+}
+
+#[test]
+fn panic_inside_an_entered_parameterization() {
+	let snapshot = tramp! { DEPTH: 5, FLAG: true => Parameterization::current() };
+
+	tramp! { NAME: "outer".to_string() => {
+		assert![catch(|| snapshot.enter(|| tramp! { LIST: vec![1] => panic!["entered"] })).is_none()];
+		assert_eq![NAME.get(), "outer"];
+		assert_eq![DEPTH.get(), 0];
+	}}
+	assert_unbound();
+}
+
+#[test]
+fn bindings_work_after_a_panic() {
+	assert![catch(|| descend(1, 3)).is_none()];
+	assert_eq![tramp! { DEPTH: 2, NAME: "again".to_string() => format!["{}{}", NAME.get(), DEPTH.get()] }, "again2"];
+	assert_unbound();
+}